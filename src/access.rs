@@ -0,0 +1,337 @@
+//! Per-client command allowlists, configured in the `--config` file:
+//!
+//! ```toml
+//! [profiles.kiosk]
+//! commands = ["play", "pause", "stop"]
+//!
+//! [profiles.admin]
+//! commands = ["*"]
+//!
+//! [[clients]]
+//! cidr = "192.168.1.50/32"
+//! profile = "admin"
+//!
+//! [[clients]]
+//! cidr = "192.168.1.0/24"
+//! profile = "kiosk"
+//!
+//! [[clients]]
+//! cert_name = "admin-laptop"
+//! profile = "admin"
+//! ```
+//!
+//! The first `[[clients]]` entry matching the client decides the profile. An
+//! entry matches by source address (`cidr`) or, under mutual TLS, by a
+//! common name or SAN of the client certificate (`cert_name`). Unmatched
+//! clients get the profile named `default`, or may run nothing if there is
+//! none. A pattern ending in `*` matches by prefix, e.g. `pi_*`. Without any
+//! `[[clients]]` every client may run every command.
+//!
+//! A `cidr` may carry an IPv6 zone, `fe80::%eth0/64`, to match link-local
+//! clients on one interface only; without a zone it matches on any interface.
+//!
+//! The entry an address matched is remembered for the most recently seen
+//! addresses, so repeated commands from a client skip the scan. The policy
+//! is built once from the config; a new policy starts with an empty cache.
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+
+use crate::netaddr;
+
+const DEFAULT_PROFILE: &str = "default";
+/// Client addresses whose matching `[[clients]]` entry is remembered
+const RULE_CACHE_SIZE: usize = 256;
+
+/// The `profiles` and `clients` sections of the config file.
+#[derive(Deserialize, Default)]
+pub struct AccessConfig {
+    #[serde(default)]
+    profiles: HashMap<String, ProfileConfig>,
+    #[serde(default)]
+    clients: Vec<ClientConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileConfig {
+    commands: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientConfig {
+    cidr: Option<String>,
+    cert_name: Option<String>,
+    profile: String,
+}
+
+/// How a `[[clients]]` entry recognises a client.
+enum Matcher {
+    Cidr(Cidr),
+    CertName(String),
+}
+
+/// Decides which commands a client may run.
+pub struct AccessPolicy {
+    rules: Vec<(Matcher, String)>,
+    profiles: HashMap<String, Vec<String>>,
+    cache: Mutex<RuleCache>,
+}
+
+impl AccessPolicy {
+    /// Builds the policy, or `None` when no client rules are configured.
+    pub fn from_config(config: AccessConfig) -> Result<Option<Self>> {
+        if config.clients.is_empty() {
+            return Ok(None);
+        }
+        let mut rules = Vec::new();
+        for client in config.clients {
+            let matcher = match (client.cidr, client.cert_name) {
+                (Some(cidr), None) => {
+                    Matcher::Cidr(Cidr::parse(&cidr).with_context(|| format!("invalid cidr '{}'", cidr))?)
+                }
+                (None, Some(name)) => Matcher::CertName(name),
+                _ => bail!("each [[clients]] entry needs exactly one of cidr and cert_name"),
+            };
+            if !config.profiles.contains_key(&client.profile) {
+                bail!("[[clients]] entry uses unknown profile '{}'", client.profile);
+            }
+            rules.push((matcher, client.profile));
+        }
+        let profiles = config.profiles.into_iter().map(|(name, profile)| (name, profile.commands)).collect();
+        Ok(Some(Self {
+            rules,
+            profiles,
+            cache: Mutex::new(RuleCache::default()),
+        }))
+    }
+
+    /// Name of the profile applying to a client, if any.
+    pub fn profile_for(&self, addr: SocketAddr, cert_names: &[String]) -> Option<&str> {
+        // Only the address decides for clients without a certificate, so their
+        // result can be reused; the port differs between connections.
+        let rule = if cert_names.is_empty() {
+            let key = (addr.ip(), scope_id(addr));
+            let cached = self.cache.lock().unwrap().get(key);
+            cached.unwrap_or_else(|| {
+                let rule = self.matching_rule(addr, cert_names);
+                self.cache.lock().unwrap().insert(key, rule);
+                rule
+            })
+        } else {
+            self.matching_rule(addr, cert_names)
+        };
+        rule.map(|index| self.rules[index].1.as_str())
+            .or_else(|| self.profiles.contains_key(DEFAULT_PROFILE).then_some(DEFAULT_PROFILE))
+    }
+
+    /// Index of the first `[[clients]]` entry matching the client.
+    fn matching_rule(&self, addr: SocketAddr, cert_names: &[String]) -> Option<usize> {
+        self.rules.iter().position(|(matcher, _)| match matcher {
+            Matcher::Cidr(cidr) => cidr.contains(addr),
+            Matcher::CertName(name) => cert_names.contains(name),
+        })
+    }
+
+    /// Every profile and its command patterns, sorted by name.
+    pub fn profiles(&self) -> BTreeMap<&str, &[String]> {
+        self.profiles.iter().map(|(name, commands)| (name.as_str(), commands.as_slice())).collect()
+    }
+
+    /// Fails with `NotPermitted` unless the client's profile allows `verb`.
+    pub fn check(&self, addr: SocketAddr, cert_names: &[String], verb: &str) -> Result<()> {
+        let Some(profile) = self.profile_for(addr, cert_names) else {
+            return Err(NotPermitted(format!("{} (no profile for {})", verb, netaddr::display_ip(addr))).into());
+        };
+        let allowed = self.profiles[profile].iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => verb.starts_with(prefix),
+            None => verb == pattern,
+        });
+        if !allowed {
+            return Err(NotPermitted(format!("{} (profile {})", verb, profile)).into());
+        }
+        Ok(())
+    }
+}
+
+/// A command the client's profile does not allow, with the verb and why.
+#[derive(Debug)]
+pub struct NotPermitted(String);
+
+impl fmt::Display for NotPermitted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Command not permitted: {}", self.0)
+    }
+}
+
+impl std::error::Error for NotPermitted {}
+
+fn scope_id(addr: SocketAddr) -> u32 {
+    match addr {
+        SocketAddr::V6(addr) => addr.scope_id(),
+        SocketAddr::V4(_) => 0,
+    }
+}
+
+/// Least recently used cache of the rule (or none) each client address matched.
+#[derive(Default)]
+struct RuleCache {
+    entries: HashMap<(IpAddr, u32), (Option<usize>, u64)>,
+    clock: u64,
+}
+
+impl RuleCache {
+    fn get(&mut self, key: (IpAddr, u32)) -> Option<Option<usize>> {
+        self.clock += 1;
+        let (rule, used) = self.entries.get_mut(&key)?;
+        *used = self.clock;
+        Some(*rule)
+    }
+
+    fn insert(&mut self, key: (IpAddr, u32), rule: Option<usize>) {
+        if self.entries.len() >= RULE_CACHE_SIZE
+            && !self.entries.contains_key(&key)
+            && let Some(oldest) = self.entries.iter().min_by_key(|(_, (_, used))| *used).map(|(key, _)| *key)
+        {
+            self.entries.remove(&oldest);
+        }
+        self.clock += 1;
+        self.entries.insert(key, (rule, self.clock));
+    }
+}
+
+/// An address range such as `10.0.0.0/8` or `fd00::/8`; a bare address is a single host.
+/// IPv6 ranges may name a zone, as in `fe80::%eth0/64` or `fe80::1%2`.
+#[derive(Clone, Debug)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u32,
+    /// Scope ID the client must come from; `None` matches any
+    zone: Option<u32>,
+}
+
+impl Cidr {
+    pub fn parse(text: &str) -> Result<Self> {
+        let (addr, prefix_len) = match text.split_once('/') {
+            Some((addr, len)) => (addr, Some(len.parse::<u32>()?)),
+            None => (text, None),
+        };
+        let (addr, zone) = match addr.split_once('%') {
+            Some((addr, zone)) => (addr.parse::<IpAddr>()?, Some(netaddr::zone_index(zone)?)),
+            None => (addr.parse::<IpAddr>()?, None),
+        };
+        if zone.is_some() && !addr.to_canonical().is_ipv6() {
+            bail!("only IPv6 addresses have a zone");
+        }
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let mut prefix_len = prefix_len.unwrap_or(max_len);
+        if prefix_len > max_len {
+            bail!("prefix length {} is longer than {}", prefix_len, max_len);
+        }
+        // An IPv4-mapped range such as ::ffff:10.0.0.0/104 is matched as the IPv4 range 10.0.0.0/8.
+        let network = addr.to_canonical();
+        if addr.is_ipv6() && network.is_ipv4() {
+            if prefix_len < 96 {
+                bail!("prefix length {} of an IPv4-mapped range is shorter than 96", prefix_len);
+            }
+            prefix_len -= 96;
+        }
+        Ok(Self {
+            network,
+            prefix_len,
+            zone,
+        })
+    }
+
+    pub fn contains(&self, addr: SocketAddr) -> bool {
+        if let Some(zone) = self.zone {
+            let SocketAddr::V6(addr) = addr else {
+                return false;
+            };
+            if addr.scope_id() != zone {
+                return false;
+            }
+        }
+        match (self.network, addr.ip().to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len).unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(clients: &str) -> AccessPolicy {
+        let config = format!("[profiles.kiosk]\ncommands = [\"play\"]\n{}", clients);
+        AccessPolicy::from_config(toml::from_str(&config).unwrap()).unwrap().unwrap()
+    }
+
+    fn addr(text: &str) -> SocketAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn zoned_cidr_matches_only_that_zone() {
+        let policy = policy("[[clients]]\ncidr = \"fe80::%2/64\"\nprofile = \"kiosk\"\n");
+        assert_eq!(policy.profile_for(addr("[fe80::1%2]:5000"), &[]), Some("kiosk"));
+        assert_eq!(policy.profile_for(addr("[fe80::1%3]:5000"), &[]), None);
+        assert_eq!(policy.profile_for(addr("[fe80::1]:5000"), &[]), None);
+    }
+
+    #[test]
+    fn unzoned_cidr_matches_scoped_clients() {
+        let policy = policy("[[clients]]\ncidr = \"fe80::1\"\nprofile = \"kiosk\"\n");
+        assert!(policy.check(addr("[fe80::1%2]:5000"), &[], "play").is_ok());
+        assert!(policy.check(addr("[fe80::1%2]:5000"), &[], "stop").is_err());
+        let denied = policy.check(addr("[fe80::2%2]:5000"), &[], "play").unwrap_err();
+        assert!(denied.to_string().contains("fe80::2%"));
+    }
+
+    #[test]
+    fn cached_matches_follow_the_client_address() {
+        let policy = policy("[[clients]]\ncidr = \"192.0.2.0/24\"\nprofile = \"kiosk\"\n");
+        for port in [5000, 5001] {
+            assert_eq!(policy.profile_for(addr(&format!("192.0.2.7:{}", port)), &[]), Some("kiosk"));
+            assert_eq!(policy.profile_for(addr(&format!("198.51.100.7:{}", port)), &[]), None);
+        }
+        assert_eq!(policy.cache.lock().unwrap().entries.len(), 2);
+
+        for host in 0..=RULE_CACHE_SIZE {
+            policy.profile_for(SocketAddr::from(([10, 0, (host / 256) as u8, host as u8], 5000)), &[]);
+        }
+        let cache = policy.cache.lock().unwrap();
+        assert_eq!(cache.entries.len(), RULE_CACHE_SIZE);
+        assert!(!cache.entries.contains_key(&(addr("192.0.2.7:0").ip(), 0)));
+    }
+
+    #[test]
+    fn mapped_ranges_match_as_ipv4() {
+        let mapped = Cidr::parse("::ffff:10.0.0.0/104").unwrap();
+        assert!(mapped.contains(addr("10.1.2.3:5000")));
+        assert!(mapped.contains(addr("[::ffff:10.1.2.3]:5000")));
+        assert!(!mapped.contains(addr("192.0.2.7:5000")));
+        assert!(Cidr::parse("::ffff:10.0.0.1").unwrap().contains(addr("10.0.0.1:5000")));
+        assert!(Cidr::parse("::ffff:10.0.0.0/95").is_err());
+        assert!(Cidr::parse("::ffff:10.0.0.0/129").is_err());
+    }
+
+    #[test]
+    fn rejects_zone_on_ipv4() {
+        assert!(Cidr::parse("192.0.2.0%2/24").is_err());
+        assert!(Cidr::parse("fe80::%2/64").is_ok());
+    }
+}
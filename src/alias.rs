@@ -0,0 +1,134 @@
+//! Command aliases, configured in the `[aliases]` section of the `--config`
+//! file. An alias is a template for one command; `{0}`, `{1}`, ... take the
+//! whitespace-separated arguments it is invoked with:
+//!
+//! ```toml
+//! [aliases]
+//! play_at = "goto {0}"
+//! intro = "goto_name intro"
+//! ```
+//!
+//! `play_at 5` then runs `goto 5`. An alias must be given exactly as many
+//! arguments as it has placeholders. The expanded command is handled like
+//! any other (allowlist, validation, history) and is not expanded again, so
+//! aliases cannot refer to each other.
+
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+
+use crate::commands;
+
+/// The configured aliases by name.
+#[derive(Default)]
+pub struct Aliases(HashMap<String, Template>);
+
+struct Template {
+    /// Literal text and argument indices, in order
+    parts: Vec<Part>,
+    arity: usize,
+}
+
+enum Part {
+    Text(String),
+    Arg(usize),
+}
+
+impl Aliases {
+    /// Parses every template, failing on the first that is malformed or
+    /// whose name is already a command.
+    pub fn from_config(aliases: HashMap<String, String>) -> Result<Self> {
+        let mut parsed = HashMap::new();
+        for (name, template) in aliases {
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                bail!("alias '{}': names are one word", name);
+            }
+            if commands::is_known(&name) {
+                bail!("alias '{}' would hide the command of that name", name);
+            }
+            let template = Template::parse(&template).map_err(|e| anyhow::anyhow!("alias '{}': {}", name, e))?;
+            parsed.insert(name, template);
+        }
+        Ok(Self(parsed))
+    }
+
+    /// The command `command` stands for, or `None` if its verb is no alias.
+    pub fn expand(&self, command: &str) -> Result<Option<String>> {
+        let command = command.trim();
+        let (verb, args) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let Some(template) = self.0.get(verb) else {
+            return Ok(None);
+        };
+        let args: Vec<&str> = args.split_whitespace().collect();
+        if args.len() != template.arity {
+            bail!("{} takes {} argument(s), got {}", verb, template.arity, args.len());
+        }
+        let expanded = template
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.as_str(),
+                Part::Arg(index) => args[*index],
+            })
+            .collect();
+        Ok(Some(expanded))
+    }
+}
+
+impl Template {
+    fn parse(template: &str) -> Result<Self> {
+        if template.trim().is_empty() {
+            bail!("empty template");
+        }
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            parts.push(Part::Text(rest[..start].to_string()));
+            let Some(len) = rest[start + 1..].find('}') else {
+                bail!("unterminated '{{' in \"{}\"", template);
+            };
+            let placeholder = &rest[start + 1..start + 1 + len];
+            let Ok(index) = placeholder.parse::<usize>() else {
+                bail!("invalid placeholder '{{{}}}' in \"{}\" (expected {{0}}, {{1}}, ...)", placeholder, template);
+            };
+            parts.push(Part::Arg(index));
+            rest = &rest[start + 1 + len + 1..];
+        }
+        parts.push(Part::Text(rest.to_string()));
+
+        // Every argument up to the highest placeholder must be used, so none is silently dropped.
+        let used: Vec<usize> = parts.iter().filter_map(|part| if let Part::Arg(index) = part { Some(*index) } else { None }).collect();
+        let arity = used.iter().max().map_or(0, |max| max + 1);
+        if let Some(missing) = (0..arity).find(|index| !used.contains(index)) {
+            bail!("\"{}\" uses {{{}}} but not {{{}}}", template, arity - 1, missing);
+        }
+        Ok(Self { parts, arity })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(entries: &[(&str, &str)]) -> Result<Aliases> {
+        Aliases::from_config(entries.iter().map(|(name, template)| (name.to_string(), template.to_string())).collect())
+    }
+
+    #[test]
+    fn expands_positional_arguments() {
+        let aliases = aliases(&[("play_at", "goto {0}"), ("swap", "move {1} {0}"), ("intro", "goto_name intro")]).unwrap();
+        assert_eq!(aliases.expand("play_at 5").unwrap().as_deref(), Some("goto 5"));
+        assert_eq!(aliases.expand("swap 3  4\n").unwrap().as_deref(), Some("move 4 3"));
+        assert_eq!(aliases.expand("intro").unwrap().as_deref(), Some("goto_name intro"));
+        assert_eq!(aliases.expand("goto 5").unwrap(), None);
+        assert!(aliases.expand("play_at").unwrap_err().to_string().contains("takes 1 argument(s), got 0"));
+        assert!(aliases.expand("intro now").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert!(aliases(&[("skip", "move {1} 1")]).err().unwrap().to_string().contains("but not {0}"));
+        assert!(aliases(&[("skip", "goto {x}")]).is_err());
+        assert!(aliases(&[("skip", "goto {0")]).is_err());
+        assert!(aliases(&[("play", "goto 1")]).err().unwrap().to_string().contains("hide the command"));
+    }
+}
@@ -0,0 +1,138 @@
+//! Fast-fails VLC commands while VLC is considered down, so clients get
+//! `ERR vlc_unavailable` at once instead of waiting for every retry to fail.
+
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Error message for commands refused while the circuit is open.
+pub const UNAVAILABLE: &str = "vlc_unavailable";
+
+/// Circuit breaker around VLC forwarding.
+///
+/// After `threshold` consecutive failures the circuit opens and commands are
+/// refused for `cooldown`. Then it is half-open: one command goes through as
+/// a trial, closing the circuit if it succeeds and reopening it if it fails.
+/// A `threshold` of zero disables the breaker.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// Value of the state gauge in the metrics.
+    pub fn gauge(self) -> u8 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::HalfOpen => 1,
+            CircuitState::Open => 2,
+        }
+    }
+}
+
+struct State {
+    circuit: CircuitState,
+    failures: u32,
+    /// When the circuit opened, or when the half-open trial started
+    since: Option<Instant>,
+    opens: u64,
+}
+
+/// Point-in-time view of the breaker, reported by `pi_status` and `/metrics`.
+#[derive(Serialize)]
+pub struct CircuitStatus {
+    pub enabled: bool,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub threshold: u32,
+    pub opens: u64,
+    pub secs_until_trial: Option<u64>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(State {
+                circuit: CircuitState::Closed,
+                failures: 0,
+                since: None,
+                opens: 0,
+            }),
+        }
+    }
+
+    /// Whether a command may go to VLC now. Moves an open circuit whose
+    /// cooldown has passed to half-open and lets the caller be the trial.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.circuit {
+            CircuitState::Closed => true,
+            // A trial that never reported back (e.g. its client went away)
+            // must not keep the circuit half-open forever.
+            CircuitState::Open | CircuitState::HalfOpen if self.cooldown_over(&state) => {
+                state.circuit = CircuitState::HalfOpen;
+                state.since = Some(Instant::now());
+                true
+            }
+            CircuitState::Open | CircuitState::HalfOpen => false,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.circuit = CircuitState::Closed;
+        state.failures = 0;
+        state.since = None;
+    }
+
+    /// Records a failed command, returning true if this opened the circuit.
+    pub fn record_failure(&self) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.failures = state.failures.saturating_add(1);
+        let open = match state.circuit {
+            CircuitState::Closed => state.failures >= self.threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+        if open {
+            state.circuit = CircuitState::Open;
+            state.since = Some(Instant::now());
+            state.opens += 1;
+        }
+        open
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let state = self.state.lock().unwrap();
+        let secs_until_trial = match state.circuit {
+            CircuitState::Open => state.since.map(|at| self.cooldown.saturating_sub(at.elapsed()).as_secs()),
+            _ => None,
+        };
+        CircuitStatus {
+            enabled: self.threshold > 0,
+            state: state.circuit,
+            consecutive_failures: state.failures,
+            threshold: self.threshold,
+            opens: state.opens,
+            secs_until_trial,
+        }
+    }
+
+    fn cooldown_over(&self, state: &State) -> bool {
+        state.since.is_none_or(|at| at.elapsed() >= self.cooldown)
+    }
+}
@@ -0,0 +1,89 @@
+//! Short-lived cache of VLC responses to read-only queries, and sharing of
+//! identical queries that are in flight at the same time.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+/// Maps a normalised query command to VLC's response for `ttl`.
+///
+/// A zero `ttl` disables the cache entirely.
+pub struct QueryCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl QueryCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    /// Returns the cached response for `key` if it is still fresh.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(stored, _)| stored.elapsed() < self.ttl)
+            .map(|(_, response)| response.clone())
+    }
+
+    pub fn insert(&self, key: String, response: String) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (stored, _)| stored.elapsed() < self.ttl);
+        entries.insert(key, (Instant::now(), response));
+    }
+
+    /// Drops every entry, used after a command that may have changed VLC state.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Outcome of a shared query; errors are kept as text so every waiter gets a copy.
+type Shared = Arc<OnceCell<Result<String, String>>>;
+
+/// Collapses concurrent identical queries into one VLC round-trip
+/// (`--coalesce-queries`). Unlike `QueryCache`, nothing outlives the request.
+pub struct QueryCoalescer {
+    enabled: bool,
+    in_flight: Mutex<HashMap<String, Shared>>,
+}
+
+impl QueryCoalescer {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `forward` for `key`, or waits for the identical query already in
+    /// flight and returns its response. If the caller running the query goes
+    /// away, one of the waiters takes over.
+    pub async fn run(&self, key: &str, forward: impl Future<Output = Result<String>>) -> Result<String> {
+        if !self.enabled {
+            return forward.await;
+        }
+        let shared = self.in_flight.lock().unwrap().entry(key.to_string()).or_default().clone();
+        let result = shared
+            .get_or_init(|| async { forward.await.map_err(|e| e.to_string()) })
+            .await
+            .clone();
+
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.get(key).is_some_and(|current| Arc::ptr_eq(current, &shared)) {
+            in_flight.remove(key);
+        }
+        result.map_err(anyhow::Error::msg)
+    }
+}
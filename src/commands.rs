@@ -0,0 +1,249 @@
+//! Self-description of the commands the controller understands, returned by
+//! `list_commands` and `GET /commands` so that UIs can discover them.
+
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use crate::{Controller, mgmt, tls};
+
+#[derive(Serialize)]
+pub struct CommandInfo {
+    pub name: String,
+    pub usage: String,
+    pub description: &'static str,
+    /// Implemented by the controller (possibly with several VLC commands) rather than passed through
+    pub synthetic: bool,
+    /// Only accepted because it is on the management allowlist
+    pub allowlisted: bool,
+    pub mutability: Mutability,
+}
+
+/// Whether a command changes playback, the playlist or the system.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Mutability {
+    ReadOnly,
+    /// Reads a setting without arguments and changes it with them, like `volume`
+    ReadOnlyWithoutArgs,
+    Mutating,
+}
+
+/// Commands the controller implements itself: (name, arguments, description, mutability).
+const SYNTHETIC_COMMANDS: &[(&str, &str, &str, Mutability)] = &[
+    ("list_commands", "", "List the known commands as JSON", Mutability::ReadOnly),
+    ("list_allowed", "", "What this controller accepts from the caller, as JSON", Mutability::ReadOnly),
+    ("version", "", "Controller version, git commit and build time as JSON (allowed for every client)", Mutability::ReadOnly),
+    ("detach", "<command>", "Acknowledge at once and run the command in the background", Mutability::ReadOnly),
+    ("get_meta", "", "Metadata and streams of the current input as JSON", Mutability::ReadOnly),
+    ("expect", "<regex> :: <command>", "Run a command and fail unless its response matches the regex", Mutability::ReadOnly),
+    ("playlist_clear", "", "Clear the playlist and confirm it is empty", Mutability::Mutating),
+    ("fullscreen", "on|off", "Set fullscreen explicitly, unconfirmed as RC cannot report it (without an argument VLC toggles it)", Mutability::Mutating),
+    ("mute", "on|off", "Mute, or restore the volume saved by the last mute", Mutability::Mutating),
+    ("is_playing", "", "true if VLC is playing, false otherwise (read from status)", Mutability::ReadOnly),
+    ("get_volume", "", "Volume in percent (VLC's 256 is 100, at most 125)", Mutability::ReadOnly),
+    ("seek_rel", "<+/-seconds>", "Seek relative to the current position", Mutability::Mutating),
+    ("swap_to", "<uri> [position_secs]", "Stop, play the URI instead and seek to the position once it plays", Mutability::Mutating),
+    ("restart_item", "", "Play the current item again from the beginning", Mutability::Mutating),
+    ("seek_pct", "<0-100>", "Seek to a percentage of the current input's length", Mutability::Mutating),
+    ("get_rate", "", "Current playback rate", Mutability::ReadOnly),
+    ("get_progress", "", "Elapsed and total seconds as JSON {time, length}, null without media", Mutability::ReadOnly),
+    ("rate_set", "<0.25-4.0>", "Set the playback rate, refusing values outside the range", Mutability::Mutating),
+    ("rate_up", "[step]", "Raise the playback rate by step (default 0.25), at most 4.0", Mutability::Mutating),
+    ("rate_down", "[step]", "Lower the playback rate by step (default 0.25), at least 0.25", Mutability::Mutating),
+    ("audio_track", "<n>", "Select an audio track from list_tracks (-1 disables audio)", Mutability::Mutating),
+    ("sub_track", "<n>", "Select a subtitle track from list_tracks (-1 disables subtitles)", Mutability::Mutating),
+    ("goto", "<position>", "Play the playlist item at this position (from 1), failing if there is none", Mutability::Mutating),
+    ("goto_name", "<text>", "Play the one playlist item whose name contains the text; returns its position", Mutability::Mutating),
+    ("load_playlist", "<path>", "Replace the playlist with an .m3u/.pls/.xspf file; returns the item count", Mutability::Mutating),
+    ("snapshot", "", "Save a snapshot of the video; returns its path with --snapshot-dir", Mutability::Mutating),
+    ("aspect", "<w:h>", "Set the video aspect ratio, e.g. 16:9 or 2.35:1", Mutability::Mutating),
+    ("crop", "<w:h|WxH+X+Y>", "Crop the video to a ratio or to a width x height + left + top geometry", Mutability::Mutating),
+    ("get_aspect", "", "Current video aspect ratio, or default", Mutability::ReadOnly),
+    ("list_tracks", "", "Audio and subtitle tracks of the current input as JSON", Mutability::ReadOnly),
+    ("stop_after_current", "[stop|pause|off]", "Stop or pause once the current item finishes", Mutability::Mutating),
+    ("loop_range", "<start> <end>", "Play playlist positions start to end (from 1) on repeat", Mutability::Mutating),
+    ("loop_clear", "", "Stop looping the range set by loop_range", Mutability::Mutating),
+];
+
+/// Commands every client may run, whatever its allowlist profile.
+pub const UNRESTRICTED_COMMANDS: &[&str] = &["version"];
+
+/// What `version` reports about this build.
+#[derive(Serialize)]
+pub struct BuildInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Abbreviated commit the binary was built from; `None` outside a git checkout
+    pub git_commit: Option<&'static str>,
+    /// The checkout had uncommitted changes
+    pub git_dirty: bool,
+    pub build_timestamp: &'static str,
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: Some(env!("VLC_CONTROL_GIT_COMMIT")).filter(|commit| !commit.is_empty()),
+        git_dirty: env!("VLC_CONTROL_GIT_DIRTY") == "true",
+        build_timestamp: env!("VLC_CONTROL_BUILD_TIMESTAMP"),
+    }
+}
+
+/// VLC RC commands that are forwarded as they are. Anything else is an
+/// unknown command, handled according to `--unknown-command`.
+const VLC_COMMANDS: &[(&str, &str, &str, Mutability)] = &[
+    ("play", "", "Start playback", Mutability::Mutating),
+    ("pause", "", "Toggle pause", Mutability::Mutating),
+    ("stop", "", "Stop playback", Mutability::Mutating),
+    ("next", "", "Next playlist item", Mutability::Mutating),
+    ("prev", "", "Previous playlist item", Mutability::Mutating),
+    ("add", "<uri>", "Add an item to the playlist and play it", Mutability::Mutating),
+    ("enqueue", "<uri>", "Add an item to the playlist", Mutability::Mutating),
+    ("delete", "<id>", "Remove an item from the playlist", Mutability::Mutating),
+    ("move", "<id> <id>", "Move a playlist item", Mutability::Mutating),
+    ("sort", "<key>", "Sort the playlist", Mutability::Mutating),
+    ("search", "[text]", "Search the playlist", Mutability::Mutating),
+    ("clear", "", "Clear the playlist", Mutability::Mutating),
+    ("repeat", "[on|off]", "Toggle repeating the current item", Mutability::Mutating),
+    ("loop", "[on|off]", "Toggle looping the playlist", Mutability::Mutating),
+    ("random", "[on|off]", "Toggle random playback", Mutability::Mutating),
+    ("seek", "<seconds>", "Seek to an absolute position", Mutability::Mutating),
+    ("fastforward", "", "Fast forward", Mutability::Mutating),
+    ("rewind", "", "Rewind", Mutability::Mutating),
+    ("faster", "", "Play faster", Mutability::Mutating),
+    ("slower", "", "Play slower", Mutability::Mutating),
+    ("normal", "", "Play at normal speed", Mutability::Mutating),
+    ("frame", "", "Advance one frame", Mutability::Mutating),
+    ("rate", "<rate>", "Set the playback rate", Mutability::Mutating),
+    ("title", "[n]", "Get or set the title of the current input", Mutability::ReadOnlyWithoutArgs),
+    ("title_n", "", "Next title", Mutability::Mutating),
+    ("title_p", "", "Previous title", Mutability::Mutating),
+    ("chapter", "[n]", "Get or set the chapter of the current input", Mutability::ReadOnlyWithoutArgs),
+    ("chapter_n", "", "Next chapter", Mutability::Mutating),
+    ("chapter_p", "", "Previous chapter", Mutability::Mutating),
+    ("f", "", "Toggle fullscreen", Mutability::Mutating),
+    ("volume", "[level]", "Get or set the volume (256 is 100%)", Mutability::ReadOnlyWithoutArgs),
+    ("volup", "[steps]", "Raise the volume", Mutability::Mutating),
+    ("voldown", "[steps]", "Lower the volume", Mutability::Mutating),
+    ("adev", "[device]", "Get or set the audio device", Mutability::ReadOnlyWithoutArgs),
+    ("achan", "[channels]", "Get or set the audio channels", Mutability::ReadOnlyWithoutArgs),
+    ("atrack", "[n]", "Get or set the audio track", Mutability::ReadOnlyWithoutArgs),
+    ("vtrack", "[n]", "Get or set the video track", Mutability::ReadOnlyWithoutArgs),
+    ("strack", "[n]", "Get or set the subtitle track", Mutability::ReadOnlyWithoutArgs),
+    ("vratio", "[ratio]", "Get or set the video aspect ratio", Mutability::ReadOnlyWithoutArgs),
+    ("vcrop", "[crop]", "Get or set the video crop", Mutability::ReadOnlyWithoutArgs),
+    ("vzoom", "[zoom]", "Get or set the video zoom", Mutability::ReadOnlyWithoutArgs),
+    ("vdeinterlace", "[on|off]", "Toggle deinterlacing", Mutability::Mutating),
+    ("vdeinterlace_mode", "[mode]", "Get or set the deinterlace mode", Mutability::ReadOnlyWithoutArgs),
+    ("key", "<hotkey>", "Simulate a hotkey", Mutability::Mutating),
+    ("logout", "", "Close the RC connection", Mutability::Mutating),
+    ("quit", "", "Quit VLC", Mutability::Mutating),
+    ("status", "", "Current input, volume and state", Mutability::ReadOnly),
+    ("info", "", "Information about the current input", Mutability::ReadOnly),
+    ("stats", "", "Playback statistics", Mutability::ReadOnly),
+    ("playlist", "", "List the playlist", Mutability::ReadOnly),
+    ("get_time", "", "Seconds elapsed in the current input", Mutability::ReadOnly),
+    ("get_length", "", "Length of the current input in seconds", Mutability::ReadOnly),
+    ("get_title", "", "Title of the current input", Mutability::ReadOnly),
+];
+
+/// True if `verb` is a synthetic or VLC command (management commands are
+/// resolved separately).
+pub fn is_known(verb: &str) -> bool {
+    SYNTHETIC_COMMANDS.iter().chain(VLC_COMMANDS).any(|(name, _, _, _)| *name == verb)
+}
+
+/// True if `command` is a read-only VLC command without arguments: those are
+/// cacheable and may reach VLC concurrently.
+pub fn is_vlc_query(command: &str) -> bool {
+    VLC_COMMANDS.iter().any(|&(name, _, _, mutability)| name == command && mutability == Mutability::ReadOnly)
+}
+
+/// True if `command` may change state, which `--read-only` refuses. Unknown
+/// commands count as mutating; `detach` and `expect` are judged by the command
+/// they run when it is dispatched.
+pub fn is_mutating(command: &str, mgmt_prefix: &str) -> bool {
+    let (verb, args) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+    if let Some(mgmt_command) = verb.strip_prefix(mgmt_prefix).and_then(mgmt::lookup) {
+        return mgmt_command.mutability() == Mutability::Mutating;
+    }
+    match SYNTHETIC_COMMANDS.iter().chain(VLC_COMMANDS).find(|(name, _, _, _)| *name == verb) {
+        Some((_, _, _, Mutability::ReadOnly)) => false,
+        Some((_, _, _, Mutability::ReadOnlyWithoutArgs)) => !args.trim().is_empty(),
+        Some((_, _, _, Mutability::Mutating)) | None => true,
+    }
+}
+
+/// Every known command; management commands are listed under `mgmt_prefix`.
+pub fn list(mgmt_prefix: &str) -> Vec<CommandInfo> {
+    let info = |name: String, usage: &str, description, synthetic, allowlisted, mutability| CommandInfo {
+        usage: if usage.is_empty() { name.clone() } else { format!("{} {}", name, usage) },
+        name,
+        description,
+        synthetic,
+        allowlisted,
+        mutability,
+    };
+
+    let mut commands = Vec::new();
+    for &(name, usage, description, mutability) in SYNTHETIC_COMMANDS {
+        commands.push(info(name.to_string(), usage, description, true, false, mutability));
+    }
+    for &(name, usage, description, mutability) in VLC_COMMANDS {
+        commands.push(info(name.to_string(), usage, description, false, false, mutability));
+    }
+    for &(name, command) in mgmt::REGISTRY {
+        let name = format!("{}{}", mgmt_prefix, name);
+        commands.push(info(name, command.usage(), command.description(), true, true, command.mutability()));
+    }
+    commands
+}
+
+/// Effective command policy for one client, returned by `list_allowed`.
+#[derive(Serialize)]
+pub struct AllowedCommands<'a> {
+    /// Known commands the client may run
+    pub commands: Vec<String>,
+    /// Profile applying to the client; `None` without `[[clients]]` rules or without a match
+    pub profile: Option<&'a str>,
+    /// Every configured profile, when per-client allowlists are in use
+    pub profiles: Option<BTreeMap<&'a str, &'a [String]>>,
+    /// `--unknown-command`: whether unrecognised commands reach VLC
+    pub unknown_commands: String,
+    pub system_commands_enabled: bool,
+    /// `--read-only`: mutating commands are refused
+    pub read_only: bool,
+    /// `--command-prefix-filter`
+    pub command_prefix: Option<&'a str>,
+}
+
+/// What `controller` accepts from `client_addr` (and its TLS certificate).
+pub fn allowed(controller: &Controller, client_addr: SocketAddr) -> AllowedCommands<'_> {
+    let cert_names = tls::peer_names();
+    let permitted = |name: &str| {
+        let disabled = controller.disable_system_commands && name.starts_with(controller.mgmt_prefix.as_str());
+        let read_only = controller.read_only && is_mutating(name, &controller.mgmt_prefix);
+        let denied = !UNRESTRICTED_COMMANDS.contains(&name)
+            && controller
+                .access
+                .as_ref()
+                .is_some_and(|access| access.check(client_addr, &cert_names, name).is_err());
+        !disabled && !read_only && !denied
+    };
+    AllowedCommands {
+        commands: list(&controller.mgmt_prefix).into_iter().map(|info| info.name).filter(|name| permitted(name)).collect(),
+        profile: controller.access.as_ref().and_then(|access| access.profile_for(client_addr, &cert_names)),
+        profiles: controller.access.as_ref().map(|access| access.profiles()),
+        unknown_commands: controller
+            .unknown_command
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default(),
+        system_commands_enabled: !controller.disable_system_commands,
+        read_only: controller.read_only,
+        command_prefix: controller.command_prefix.as_deref(),
+    }
+}
@@ -0,0 +1,136 @@
+//! Optional TOML configuration file (`--config`).
+//!
+//! Top-level keys mirror the long command-line options, e.g.
+//! `vlc_address = "${VLC_HOST}:54322"` for `--vlc-address`. String values may
+//! reference environment variables as `${VAR}`. Options given on the command
+//! line take precedence over the file.
+//!
+//! The `[profiles.*]` and `[[clients]]` sections are not options; they hold
+//! the per-client allowlists described in `access`. `[response_timeouts]`
+//! maps VLC command verbs to their own response timeout in milliseconds,
+//! overriding `vlc_response_timeout_ms`:
+//!
+//! ```toml
+//! vlc_response_timeout_ms = 2000
+//!
+//! [response_timeouts]
+//! playlist = 30000
+//! ```
+//!
+//! `[response_transforms]` rewrites VLC's replies per command verb, as
+//! described in `transform`, and `[aliases]` defines command templates, as
+//! described in `alias`.
+
+use anyhow::{Context, Result, bail};
+use clap::CommandFactory;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::Duration;
+use toml::{Table, Value};
+
+use crate::Args;
+use crate::access::AccessConfig;
+use crate::alias::Aliases;
+use crate::transform::ResponseTransforms;
+
+/// Sections that do not map to command-line options.
+const SECTIONS: &[&str] = &["profiles", "clients", "response_timeouts", "response_transforms", "aliases"];
+
+/// Reads the file at `path` and converts its top-level settings into
+/// command-line arguments to be parsed ahead of the real ones.
+pub fn load_args(path: &Path) -> Result<Vec<OsString>> {
+    to_args(&read(path)?).with_context(|| format!("in config {}", path.display()))
+}
+
+/// Reads the per-client allowlist sections of the file at `path`.
+pub fn load_access(path: &Path) -> Result<AccessConfig> {
+    let mut table = read(path)?;
+    table.retain(|key, _| SECTIONS.contains(&key));
+    table.try_into().with_context(|| format!("in config {}", path.display()))
+}
+
+/// Reads the per-command response timeouts of the file at `path`.
+pub fn load_response_timeouts(path: &Path) -> Result<HashMap<String, Duration>> {
+    let Some(section) = read(path)?.remove("response_timeouts") else {
+        return Ok(HashMap::new());
+    };
+    let timeouts: HashMap<String, u64> =
+        section.try_into().with_context(|| format!("in [response_timeouts] of config {}", path.display()))?;
+    Ok(timeouts.into_iter().map(|(verb, ms)| (verb, Duration::from_millis(ms))).collect())
+}
+
+/// Reads the per-command response transformations of the file at `path`.
+pub fn load_response_transforms(path: &Path) -> Result<ResponseTransforms> {
+    let Some(section) = read(path)?.remove("response_transforms") else {
+        return Ok(ResponseTransforms::default());
+    };
+    section.try_into().with_context(|| format!("in [response_transforms] of config {}", path.display()))
+}
+
+/// Reads the command aliases of the file at `path`.
+pub fn load_aliases(path: &Path) -> Result<Aliases> {
+    let Some(section) = read(path)?.remove("aliases") else {
+        return Ok(Aliases::default());
+    };
+    let aliases: HashMap<String, String> =
+        section.try_into().with_context(|| format!("in [aliases] of config {}", path.display()))?;
+    Aliases::from_config(aliases).with_context(|| format!("in [aliases] of config {}", path.display()))
+}
+
+fn read(path: &Path) -> Result<Table> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading config {}", path.display()))?;
+    text.parse().with_context(|| format!("parsing config {}", path.display()))
+}
+
+fn to_args(table: &Table) -> Result<Vec<OsString>> {
+    let command = Args::command();
+    let mut args = Vec::new();
+
+    for (key, value) in table {
+        if SECTIONS.contains(&key.as_str()) {
+            continue;
+        }
+        let option = key.replace('_', "-");
+        let known = command
+            .get_arguments()
+            .any(|arg| arg.get_long() == Some(option.as_str()) && !matches!(option.as_str(), "config" | "test-config"));
+        if !known {
+            bail!("unknown setting '{}'", key);
+        }
+
+        let flag = OsString::from(format!("--{}", option));
+        match value {
+            Value::Boolean(true) => args.push(flag),
+            Value::Boolean(false) => {}
+            Value::String(s) => {
+                let expanded = expand_env(s).with_context(|| format!("setting '{}'", key))?;
+                args.extend([flag, expanded.into()]);
+            }
+            Value::Integer(n) => args.extend([flag, n.to_string().into()]),
+            Value::Float(n) => args.extend([flag, n.to_string().into()]),
+            other => bail!("setting '{}' must be a string, number or boolean, not {}", key, other.type_str()),
+        }
+    }
+    Ok(args)
+}
+
+/// Replaces every `${VAR}` in `value` with the variable's value, failing if
+/// it is unset.
+pub fn expand_env(value: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let Some(len) = rest[start + 2..].find('}') else {
+            bail!("unterminated '${{' in \"{}\"", value);
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        let var = std::env::var(name).with_context(|| format!("environment variable {} is not set", name))?;
+        expanded.push_str(&var);
+        rest = &rest[start + 2 + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
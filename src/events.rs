@@ -0,0 +1,87 @@
+//! `--connection-events`: one JSON line per TCP connection opened, closed or
+//! rejected, and per command the allowlist refused, so monitoring can spot
+//! scanning or misbehaving clients:
+//!
+//! ```text
+//! {"timestamp_ms":1760000000000,"event":"opened","transport":"tcp","client_addr":"192.168.1.20:51234"}
+//! {"timestamp_ms":1760000000100,"event":"rejected","transport":"tcp","client_addr":"192.168.1.21:40112","reason":"tls_handshake_failed"}
+//! {"timestamp_ms":1760000005230,"event":"closed","transport":"tcp","client_addr":"192.168.1.20:51234","reason":"disconnected","duration_ms":5230}
+//! ```
+//!
+//! `reason` of a rejection is the `reason_code` the log line carries.
+//! Rejections are also counted in `vlc_control_rejections_total`.
+
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::Transport;
+use crate::sink::JsonLineSink;
+
+#[derive(Serialize)]
+struct ConnectionEvent<'a> {
+    /// Milliseconds since the Unix epoch
+    timestamp_ms: u128,
+    event: &'static str,
+    transport: &'static str,
+    client_addr: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Where connection events go; emitting is a no-op without `--connection-events`.
+pub struct ConnectionEvents {
+    sink: Option<JsonLineSink>,
+}
+
+impl ConnectionEvents {
+    /// Needs a Tokio runtime when `path` is set.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            sink: path.map(JsonLineSink::new),
+        }
+    }
+
+    pub fn opened(&self, transport: Transport, client_addr: SocketAddr) {
+        self.emit("opened", transport, client_addr, None, None, None);
+    }
+
+    /// The client went away, or serving it failed with `error`.
+    pub fn closed(&self, transport: Transport, client_addr: SocketAddr, duration: Duration, error: Option<&anyhow::Error>) {
+        let reason = if error.is_some() { "error" } else { "disconnected" };
+        self.emit("closed", transport, client_addr, Some(reason), Some(duration), error.map(ToString::to_string));
+    }
+
+    /// The connection was refused, or a command of it was; `reason` is a rejection code.
+    pub fn rejected(&self, transport: Transport, client_addr: SocketAddr, reason: &str) {
+        self.emit("rejected", transport, client_addr, Some(reason), None, None);
+    }
+
+    fn emit(
+        &self,
+        event: &'static str,
+        transport: Transport,
+        client_addr: SocketAddr,
+        reason: Option<&str>,
+        duration: Option<Duration>,
+        error: Option<String>,
+    ) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        sink.emit(&ConnectionEvent {
+            timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis(),
+            event,
+            transport: transport.as_str(),
+            client_addr,
+            reason,
+            duration_ms: duration.map(|duration| duration.as_millis() as u64),
+            error,
+        });
+    }
+}
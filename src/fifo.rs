@@ -0,0 +1,73 @@
+//! `--fifo`: a one-way local control channel. Each line written to the named
+//! pipe is processed like a TCP command; replies are only logged.
+
+use anyhow::{Context, Result, bail};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::BufReader;
+use tracing::{debug, info, warn};
+
+use crate::{Controller, Frame, Transport, log_received_command, process_command, read_frame, reject_oversized_frame};
+
+/// Address FIFO commands are attributed to in logs, the history and allowlists.
+const FIFO_CLIENT_ADDR: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 0);
+
+/// Fails unless `path` is a named pipe.
+pub fn check(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        let metadata = std::fs::metadata(path).with_context(|| format!("--fifo {}", path.display()))?;
+        if !metadata.file_type().is_fifo() {
+            bail!("--fifo {} is not a named pipe (create it with mkfifo)", path.display());
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        bail!("--fifo is not supported on this platform")
+    }
+}
+
+/// Reads commands from the pipe for as long as the controller runs. When the
+/// last writer closes it, the pipe is opened again to wait for the next one.
+#[cfg(unix)]
+pub async fn run(path: &Path, controller: Arc<Controller>) -> Result<()> {
+    info!(path = %path.display(), "Reading commands from FIFO");
+    let mut line = Vec::new();
+    loop {
+        // Opened without blocking, so waiting for a writer does not hold up shutdown.
+        let receiver = tokio::net::unix::pipe::OpenOptions::new()
+            .open_receiver(path)
+            .with_context(|| format!("opening FIFO {}", path.display()))?;
+        let mut reader = BufReader::new(receiver);
+
+        loop {
+            line.clear();
+            let result = match read_frame(&mut reader, &mut line, controller.max_line_length).await? {
+                Frame::Eof => break,
+                Frame::Oversized | Frame::CommandTooLarge => {
+                    Err(reject_oversized_frame(line.len(), controller.max_line_length))
+                }
+                Frame::Line if line.trim_ascii().is_empty() => continue,
+                Frame::Line => {
+                    log_received_command(Transport::Fifo, FIFO_CLIENT_ADDR, &line, &controller);
+                    process_command(&line, FIFO_CLIENT_ADDR, Transport::Fifo, &controller).await
+                }
+            };
+            let command = String::from_utf8_lossy(&line);
+            match result {
+                Ok(response) => debug!(command = %command.trim(), response = %response, "FIFO command finished"),
+                Err(e) => warn!(command = %command.trim(), error = %e, "FIFO command failed"),
+            }
+        }
+        debug!(path = %path.display(), "FIFO writers closed, reopening");
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn run(_path: &Path, _controller: Arc<Controller>) -> Result<()> {
+    bail!("--fifo is not supported on this platform")
+}
@@ -0,0 +1,62 @@
+//! Bounded in-memory record of recently processed commands.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of commands kept; also the most `pi_history` returns.
+pub const HISTORY_CAPACITY: usize = 100;
+
+/// One processed command as reported by `pi_history`.
+#[derive(Clone, Serialize)]
+pub struct HistoryEntry {
+    /// Milliseconds since the Unix epoch when processing finished
+    pub timestamp_ms: u128,
+    pub verb: String,
+    pub client_addr: SocketAddr,
+    pub outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Ring buffer of the last `HISTORY_CAPACITY` commands.
+pub struct History {
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(HISTORY_CAPACITY)),
+        }
+    }
+
+    /// Adds an entry for a finished command and returns it.
+    pub fn record(&self, verb: &str, client_addr: SocketAddr, error: Option<String>) -> HistoryEntry {
+        let entry = HistoryEntry {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_millis())
+                .unwrap_or_default(),
+            verb: verb.to_string(),
+            client_addr,
+            outcome: if error.is_none() { "ok" } else { "error" },
+            error,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == HISTORY_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(entry.clone());
+        entry
+    }
+
+    /// Returns up to `count` of the most recent entries, oldest first.
+    pub fn recent(&self, count: usize) -> Vec<HistoryEntry> {
+        let entries = self.entries.lock().unwrap();
+        let skip = entries.len().saturating_sub(count);
+        entries.iter().skip(skip).cloned().collect()
+    }
+}
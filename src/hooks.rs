@@ -0,0 +1,105 @@
+//! User commands run on lifecycle events (`--on-vlc-down`, `--on-vlc-up`,
+//! `--on-shutdown`), e.g. to flash an LED when VLC becomes unreachable.
+//!
+//! A hook is a program followed by its arguments, separated by whitespace; it
+//! is not run through a shell. The event name is passed in the
+//! `VLC_CONTROL_EVENT` environment variable.
+
+use std::sync::Mutex;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Environment variable carrying the event name to the hook.
+const EVENT_VAR: &str = "VLC_CONTROL_EVENT";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookEvent {
+    VlcDown,
+    VlcUp,
+    Shutdown,
+}
+
+impl HookEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::VlcDown => "vlc_down",
+            HookEvent::VlcUp => "vlc_up",
+            HookEvent::Shutdown => "shutdown",
+        }
+    }
+}
+
+/// The configured hooks and the VLC reachability they react to.
+pub struct Hooks {
+    on_vlc_down: Option<String>,
+    on_vlc_up: Option<String>,
+    on_shutdown: Option<String>,
+    /// Outcome of the last VLC command; `None` until the first one
+    vlc_reachable: Mutex<Option<bool>>,
+}
+
+impl Hooks {
+    pub fn new(on_vlc_down: Option<String>, on_vlc_up: Option<String>, on_shutdown: Option<String>) -> Self {
+        Self {
+            on_vlc_down,
+            on_vlc_up,
+            on_shutdown,
+            vlc_reachable: Mutex::new(None),
+        }
+    }
+
+    /// Records whether a VLC command got through, running `on_vlc_down` or
+    /// `on_vlc_up` when that differs from the previous command.
+    pub fn record_vlc(&self, reachable: bool) {
+        if let Some(event) = self.transition(reachable) {
+            self.fire(event);
+        }
+    }
+
+    fn transition(&self, reachable: bool) -> Option<HookEvent> {
+        let previous = self.vlc_reachable.lock().unwrap().replace(reachable);
+        if previous == Some(reachable) {
+            return None;
+        }
+        Some(if reachable { HookEvent::VlcUp } else { HookEvent::VlcDown })
+    }
+
+    /// Starts the hook for `event` in the background, if one is configured.
+    /// The returned task finishes once the hook has exited and been logged.
+    pub fn fire(&self, event: HookEvent) -> Option<JoinHandle<()>> {
+        let hook = match event {
+            HookEvent::VlcDown => &self.on_vlc_down,
+            HookEvent::VlcUp => &self.on_vlc_up,
+            HookEvent::Shutdown => &self.on_shutdown,
+        };
+        let mut words = hook.as_deref()?.split_whitespace();
+        let program = words.next()?;
+        let mut command = Command::new(program);
+        command.args(words).env(EVENT_VAR, event.name());
+
+        let hook = hook.clone().unwrap_or_default();
+        Some(tokio::spawn(async move {
+            match command.status().await {
+                Ok(status) if status.success() => info!(event = event.name(), hook = %hook, "Hook completed"),
+                Ok(status) => warn!(event = event.name(), hook = %hook, exit_code = status.code(), "Hook failed"),
+                Err(e) => warn!(event = event.name(), hook = %hook, error = %e, "Failed to run hook"),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fires_only_when_reachability_changes() {
+        let hooks = Hooks::new(None, None, None);
+        assert_eq!(hooks.transition(true), Some(HookEvent::VlcUp));
+        assert_eq!(hooks.transition(true), None);
+        assert_eq!(hooks.transition(false), Some(HookEvent::VlcDown));
+        assert_eq!(hooks.transition(false), None);
+        assert_eq!(hooks.transition(true), Some(HookEvent::VlcUp));
+    }
+}
@@ -0,0 +1,46 @@
+//! JSON flavour of the TCP line protocol, chosen by `--tcp-auto-detect` when
+//! a connection starts with `{`. Each line is one request object and gets
+//! one reply object:
+//!
+//! ```text
+//! {"id": 1, "command": "volume 200"}
+//! {"id":1,"ok":true,"response":""}
+//! ```
+//!
+//! `id` is optional and may be any JSON value; it is returned as given.
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Request {
+    #[serde(default)]
+    pub id: Value,
+    pub command: String,
+}
+
+/// Parses one request line. A command holding a control character is
+/// refused: JSON can escape a line break into it, which would reach VLC as a
+/// second command that was never checked.
+pub fn parse(line: &[u8]) -> Result<Request> {
+    let request: Request =
+        serde_json::from_slice(line.trim_ascii()).map_err(|e| anyhow::anyhow!("Invalid JSON request: {}", e))?;
+    if request.command.contains(char::is_control) {
+        bail!("Invalid JSON request: command contains a control character");
+    }
+    Ok(request)
+}
+
+/// The reply line for the request with `id`.
+pub fn reply(id: &Value, result: &Result<String>) -> String {
+    let mut reply = match result {
+        Ok(response) => json!({ "ok": true, "response": response }),
+        Err(e) => json!({ "ok": false, "error": e.to_string() }),
+    };
+    if !id.is_null() {
+        reply["id"] = id.clone();
+    }
+    format!("{}\n", reply)
+}
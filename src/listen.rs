@@ -0,0 +1,58 @@
+//! Listener setup, optionally with `SO_REUSEPORT` (`--reuse-port`).
+//!
+//! With `SO_REUSEPORT` several controller processes can bind the same TCP and
+//! UDP ports and the kernel spreads connections and datagrams across them.
+//! Each process keeps its own state (query cache, history, watchdog, mute
+//! volume, pending `stop_after_current`), so clients may see a different
+//! instance on every connection or datagram.
+
+use anyhow::{Context, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, UdpSocket};
+
+/// Whether this platform lets us set `SO_REUSEPORT`.
+pub const REUSE_PORT_SUPPORTED: bool = cfg!(all(unix, not(any(target_os = "solaris", target_os = "illumos"))));
+
+pub async fn bind_tcp(addr: &str, reuse_port: bool) -> Result<TcpListener> {
+    if !reuse_port {
+        return Ok(TcpListener::bind(addr).await?);
+    }
+    let addr = resolve(addr).await?;
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    set_reuse_port(&socket)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+pub async fn bind_udp(addr: &str, reuse_port: bool) -> Result<UdpSocket> {
+    if !reuse_port {
+        return Ok(UdpSocket::bind(addr).await?);
+    }
+    let addr = resolve(addr).await?;
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    set_reuse_port(&socket)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+async fn resolve(addr: &str) -> Result<SocketAddr> {
+    tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .with_context(|| format!("{} did not resolve to an address", addr))
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(socket: &Socket) -> Result<()> {
+    Ok(socket.set_reuse_port(true)?)
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_socket: &Socket) -> Result<()> {
+    anyhow::bail!("SO_REUSEPORT is not supported on this platform")
+}
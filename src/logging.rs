@@ -0,0 +1,139 @@
+//! Log output setup, the filter `pi_set_log_level` swaps at runtime,
+//! `--log-sample-every` sampling of per-command debug logs, and debug logs
+//! for the TCP connections that ask for them with `#verbose`.
+
+use anyhow::Result;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::net::SocketAddr;
+use tracing::{Level, Metadata, Span, Subscriber, info, info_span};
+use tracing_subscriber::filter::dynamic_filter_fn;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Registry, reload};
+
+use crate::netaddr::ClientAddr;
+use crate::{Args, COMMAND_LOG_TARGET, LogLevel};
+
+/// First line of a TCP connection that wants its commands logged at debug level.
+pub const VERBOSE_DIRECTIVE: &[u8] = b"#verbose";
+/// Name of the span around the commands of a `#verbose` connection
+const VERBOSE_SPAN: &str = "connection";
+
+/// Handle on the installed log filter.
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// Filter directives from `RUST_LOG`, which take precedence over `--log-level`
+    env_directives: Option<String>,
+    configured: LogLevel,
+    log_commands: bool,
+}
+
+/// Installs the global subscriber, filtered by `RUST_LOG` if set and by
+/// `--log-level` otherwise.
+pub fn init(args: &Args) -> Result<LogFilter> {
+    let env_directives = std::env::var("RUST_LOG").ok();
+    let (layer, handle) = reload::Layer::new(build(env_directives.as_deref(), args.log_level, args.log_commands)?);
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(tracing_subscriber::fmt::layer().with_ansi(args.color.enabled()).with_filter(dynamic_filter_fn(sampled)))
+        .init();
+    Ok(LogFilter {
+        handle,
+        env_directives,
+        configured: args.log_level,
+        log_commands: args.log_commands,
+    })
+}
+
+impl LogFilter {
+    /// Logs at `level` until `reset`, whatever `RUST_LOG` or `--log-level` said.
+    pub fn set(&self, level: LogLevel) -> Result<()> {
+        self.handle.reload(build(None, level, self.log_commands)?)?;
+        info!(level = level.as_filter_str(), "Log level changed at runtime");
+        Ok(())
+    }
+
+    /// Goes back to the filter the controller was started with.
+    pub fn reset(&self) -> Result<()> {
+        self.handle.reload(build(self.env_directives.as_deref(), self.configured, self.log_commands)?)?;
+        info!("Log level reset to the configured filter");
+        Ok(())
+    }
+}
+
+fn build(env_directives: Option<&str>, level: LogLevel, log_commands: bool) -> Result<EnvFilter> {
+    let mut filter = match env_directives {
+        Some(directives) => EnvFilter::new(directives),
+        None => EnvFilter::new(format!("vlc_control={}", level.as_filter_str())),
+    };
+    if log_commands {
+        // The command log has its own target so it shows whatever the level.
+        filter = filter.add_directive(format!("{}=info", COMMAND_LOG_TARGET).parse()?);
+    }
+    // Already at least as verbose as a `#verbose` connection asks for.
+    if env_directives.is_some() || !matches!(level, LogLevel::Debug | LogLevel::Trace) {
+        filter = filter.add_directive(format!("vlc_control[{}{{verbose=true}}]=debug", VERBOSE_SPAN).parse()?);
+    }
+    Ok(filter)
+}
+
+/// The span to run the commands of a `#verbose` connection in.
+pub fn verbose_span(addr: SocketAddr) -> Span {
+    info_span!(VERBOSE_SPAN, verbose = true, client_addr = %ClientAddr(addr))
+}
+
+tokio::task_local! {
+    /// Whether the command being handled keeps its debug and trace events
+    static SAMPLED: bool;
+}
+
+/// Picks the commands whose debug and trace events are logged.
+pub struct Sampler {
+    every: u64,
+    commands: AtomicU64,
+}
+
+impl Sampler {
+    /// Keeps the detailed logs of one command in `every`; 0 and 1 keep all.
+    pub fn new(every: u64) -> Self {
+        Self {
+            every,
+            commands: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `command`, dropping its debug and trace events unless it is sampled.
+    pub async fn scope<F: Future>(&self, command: F) -> F::Output {
+        if self.every <= 1 {
+            return command.await;
+        }
+        let sampled = self.commands.fetch_add(1, Ordering::Relaxed).is_multiple_of(self.every);
+        SAMPLED.scope(sampled, command).await
+    }
+}
+
+/// Info and above always pass, as does anything logged outside a command or
+/// for a `#verbose` connection.
+fn sampled<S: Subscriber + for<'a> LookupSpan<'a>>(metadata: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+    *metadata.level() <= Level::INFO
+        || SAMPLED.try_with(|sampled| *sampled).unwrap_or(true)
+        || cx.lookup_current().is_some_and(|span| span.scope().any(|span| span.name() == VERBOSE_SPAN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn samples_one_command_in_every() {
+        let sampler = Sampler::new(3);
+        let mut kept = Vec::new();
+        for _ in 0..6 {
+            kept.push(sampler.scope(async { SAMPLED.get() }).await);
+        }
+        assert_eq!(kept, [true, false, false, true, false, false]);
+        assert!(SAMPLED.try_with(|sampled| *sampled).is_err());
+    }
+}
@@ -1,24 +1,166 @@
 use anyhow::Result;
 use clap::{Parser, ValueEnum};
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::process::Command;
-use std::time::Duration;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
-use tracing::{debug, error, info, warn};
-use tracing_subscriber;
-use tracing_subscriber::EnvFilter;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tracing::{Instrument, Span, debug, error, info, warn};
+
+mod access;
+mod alias;
+mod breaker;
+mod cache;
+mod commands;
+mod config;
+mod events;
+mod fifo;
+mod history;
+mod hooks;
+mod jsonl;
+mod listen;
+mod logging;
+mod metrics;
+mod mgmt;
+mod netaddr;
+mod pidfile;
+mod playlist_file;
+mod poller;
+mod privileges;
+mod proxy;
+mod rc;
+mod shutdown;
+mod sink;
+mod snapshot;
+#[cfg(test)]
+mod testing;
+mod vlc;
+mod tls;
+mod transform;
+mod validate;
+mod watchdog;
+mod web;
+
+use access::AccessPolicy;
+use alias::Aliases;
+use breaker::CircuitBreaker;
+use cache::{QueryCache, QueryCoalescer};
+use events::ConnectionEvents;
+use history::History;
+use hooks::{HookEvent, Hooks};
+use logging::{LogFilter, Sampler};
+use metrics::Metrics;
+use netaddr::{ClientAddr, PortRange};
+use pidfile::{PidFile, PidFileConflict};
+use poller::{EndAction, LoopRange, StopAfterCurrent};
+use shutdown::Shutdown;
+use sink::JsonLineSink;
+use transform::ResponseTransforms;
+use vlc::{ErrorClass, RetryBudget, SrvResolver, VlcClient, VlcOptions, VlcProtocol, VlcTransport};
+use watchdog::Watchdog;
 
 #[derive(Parser)]
 #[command(name = "vlc-control")]
 #[command(about = "A VLC remote control server")]
+#[command(args_override_self = true)]
 struct Args {
+    /// TOML file with default values for any of these options
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Check the options and the --config file, print any problems and exit (non-zero if
+    /// there are any) without starting the servers
+    #[arg(long)]
+    test_config: bool,
+
     /// logging level
     #[arg(short, long, value_enum, default_value_t = LogLevel::Info)]
     log_level: LogLevel,
     
+    /// Colour the log output with ANSI escapes
+    #[arg(long, value_enum, default_value_t = LogColor::Auto)]
+    color: LogColor,
+
+    /// Debugging aid: before replying, send TCP and UDP clients "ECHO <command>" with the
+    /// command as parsed (trimmed, invalid UTF-8 replaced, control characters escaped)
+    #[arg(long)]
+    echo_commands: bool,
+
+    /// After each command finishes, report it as a JSON line to this Unix socket, or append it
+    /// to this file if it is not a socket (for test harnesses)
+    #[arg(long)]
+    completion_sink: Option<PathBuf>,
+
+    /// Report TCP connections opened, closed and rejected, and commands refused by the
+    /// allowlist, as JSON lines to this Unix socket or file (as for --completion-sink)
+    #[arg(long)]
+    connection_events: Option<PathBuf>,
+
+    /// Log debug and trace events for only 1 in N commands (1 = all), to cut logging overhead
+    /// under sustained load; info, warnings and errors are always logged
+    #[arg(long, default_value_t = 1)]
+    log_sample_every: u64,
+
+    /// Log every received command at info level, whatever --log-level says
+    #[arg(long)]
+    log_commands: bool,
+
     /// VLC server address
     #[arg(long, default_value = "127.0.0.1:54322")]
     vlc_address: String,
+
+    /// Give up connecting to VLC after this many milliseconds (default: the OS connect timeout)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    vlc_connect_timeout_ms: Option<u64>,
+
+    /// Give up on a VLC command attempt after this many milliseconds; the config file's
+    /// [response_timeouts] section sets it per command verb
+    #[arg(long)]
+    vlc_response_timeout_ms: Option<u64>,
+
+    /// Drop the VLC connection and fail the command once a reply grows past this many bytes
+    /// without its prompt (0 = unlimited)
+    #[arg(long, default_value_t = DEFAULT_VLC_MAX_RESPONSE_BYTES)]
+    vlc_max_response_bytes: u64,
+
+    /// DNS SRV name (e.g. _vlc._tcp.example.com) to look VLC's address up from instead of
+    /// --vlc-address; looked up again once the record's TTL has passed
+    #[arg(long)]
+    vlc_srv: Option<String>,
+
+    /// Backup VLC address, tried when the primary stays unreachable after retries
+    #[arg(long)]
+    vlc_address_fallback: Option<String>,
+
+    /// Local address outbound VLC connections are bound to (multi-homed hosts)
+    #[arg(long)]
+    vlc_bind_address: Option<IpAddr>,
+
+    /// VLC's RC interface prints no banner/prompt (e.g. --rc-quiet); send commands immediately
+    #[arg(long)]
+    vlc_no_banner: bool,
+
+    /// VLC control interface to use
+    #[arg(long, value_enum, default_value_t = VlcProtocol::Rc)]
+    vlc_protocol: VlcProtocol,
+
+    /// Password of VLC's HTTP interface (--http-password)
+    #[arg(long)]
+    vlc_password: Option<String>,
+
+    /// Program and arguments to start VLC with under --vlc-protocol stdin [default: "vlc --intf rc --rc-fake-tty"]
+    #[arg(long)]
+    vlc_command: Option<String>,
     
     /// TCP listening address
     #[arg(long, default_value = "0.0.0.0:55550")]
@@ -27,6 +169,193 @@ struct Args {
     /// UDP listening address
     #[arg(long, default_value = "0.0.0.0:55551")]
     udp_address: String,
+
+    /// Also read commands, one per line, from this named pipe; replies are only logged
+    #[arg(long)]
+    fifo: Option<PathBuf>,
+
+    /// How shutdown/reboot gain root privileges
+    #[arg(long, value_enum, default_value_t = PrivilegeEscalation::Sudo)]
+    privilege_escalation: PrivilegeEscalation,
+
+    /// Refuse every management command (restart_vlc, shutdown, reboot, ...) with "ERR disabled"
+    #[arg(long)]
+    disable_system_commands: bool,
+
+    /// Only answer queries (status, playlist, info, get_*, ...); refuse commands that change playback or the system with "ERR read_only"
+    #[arg(long)]
+    read_only: bool,
+
+    /// Another vlc-control instance (TCP address) that successful VLC commands are mirrored to
+    #[arg(long)]
+    mirror_address: Option<String>,
+
+    /// Largest frame (TCP line or UDP datagram, including the newline) read from a client
+    #[arg(long, default_value_t = DEFAULT_MAX_LINE_LENGTH)]
+    max_line_length: usize,
+
+    /// Largest single command (including the newline) accepted for processing; TCP lines ending
+    /// in `\` continue on the next line and count as one command
+    #[arg(long, default_value_t = DEFAULT_MAX_COMMAND_SIZE)]
+    max_command_size: usize,
+
+    /// Serve repeated read-only queries (status, playlist, ...) from a cache for this long (0 = off)
+    #[arg(long, default_value_t = 0)]
+    query_cache_ms: u64,
+
+    /// Let concurrent identical read-only queries share one VLC round-trip and its response
+    #[arg(long)]
+    coalesce_queries: bool,
+
+    /// Restart VLC (as the restart_vlc management command) after this many consecutive failed VLC commands (0 = off)
+    #[arg(long, default_value_t = 0)]
+    watchdog_failures: u32,
+
+    /// Failures only count as consecutive if they happen within this many seconds
+    #[arg(long, default_value_t = 60)]
+    watchdog_window_secs: u64,
+
+    /// Minimum seconds between two watchdog restarts
+    #[arg(long, default_value_t = 300)]
+    watchdog_cooldown_secs: u64,
+
+    /// Answer "ERR vlc_unavailable" without contacting VLC after this many consecutive failed VLC commands (0 = off)
+    #[arg(long, default_value_t = 0)]
+    circuit_failures: u32,
+
+    /// Seconds the circuit stays open before one command is let through to test VLC
+    #[arg(long, default_value_t = 30)]
+    circuit_cooldown_secs: u64,
+
+    /// Send "vlc-control <version>" to TCP clients as soon as they connect
+    #[arg(long)]
+    client_greeting: bool,
+
+    /// Read-only queries that may be in flight to VLC at once; other commands run one at a time
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    max_concurrent_queries: u32,
+
+    /// UDP datagrams handled at once; datagrams from one source are still handled in order
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    udp_concurrency: u32,
+
+    /// Only accept UDP datagrams from these source ports (e.g. 40000-40100); others are dropped unanswered
+    #[arg(long)]
+    udp_source_ports: Option<PortRange>,
+
+    /// Expect a PROXY protocol v1 header at the start of every TCP connection
+    #[arg(long)]
+    accept_proxy_protocol: bool,
+
+    /// Tell TCP clients apart by their first bytes: a PROXY v1 header from --trusted-proxies
+    /// is read as with --accept-proxy-protocol, and a connection whose first command starts
+    /// with `{` speaks JSON lines ({"command": "..."} in, {"ok": ..., "response": ...} out)
+    #[arg(long)]
+    tcp_auto_detect: bool,
+
+    /// With --tcp-auto-detect, the proxies (addresses or CIDR ranges, comma-separated) whose
+    /// connections may open with a PROXY v1 header. Their connections wait up to 1s for it
+    /// before the greeting; a PROXY header from anywhere else is not honoured
+    #[arg(long, value_delimiter = ',', value_parser = access::Cidr::parse, requires = "tcp_auto_detect")]
+    trusted_proxies: Vec<access::Cidr>,
+
+    /// Prefix that marks management commands (restart_vlc, shutdown, reboot, status, history, quit)
+    #[arg(long, default_value = DEFAULT_MGMT_PREFIX, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    mgmt_prefix: String,
+
+    /// Failure classes that are retried (connect, io, protocol); others fail the command at once
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "connect,io")]
+    vlc_retry_on: Vec<ErrorClass>,
+
+    /// Retries against VLC allowed per --vlc-retry-budget-window-secs across all commands;
+    /// once spent, failures are returned without retrying (default: unlimited)
+    #[arg(long)]
+    vlc_retry_budget: Option<u32>,
+
+    /// Window over which --vlc-retry-budget refills
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    vlc_retry_budget_window_secs: u64,
+
+    /// Log repeated identical VLC failures as one summary per this many seconds (0 logs each)
+    #[arg(long, default_value_t = 60)]
+    vlc_error_log_interval_secs: u64,
+
+    /// Set SO_REUSEPORT on the TCP and UDP listeners so several instances can share the ports.
+    /// Each instance keeps its own state (cache, history, watchdog, ...)
+    #[arg(long)]
+    reuse_port: bool,
+
+    /// Only accept commands starting with this prefix (e.g. `vlc:`), which is stripped before
+    /// processing; others are rejected so traffic meant for another service never reaches VLC
+    #[arg(long, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    command_prefix_filter: Option<String>,
+
+    /// What to do with commands that are neither VLC nor controller commands
+    #[arg(long, value_enum, default_value_t = UnknownCommand::Forward)]
+    unknown_command: UnknownCommand,
+
+    /// Serve TCP over TLS with this PEM certificate chain (needs --tls-key)
+    #[arg(long)]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long)]
+    tls_key: Option<PathBuf>,
+
+    /// Require TCP clients to present a certificate signed by this PEM CA (mutual TLS)
+    #[arg(long)]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Directory VLC saves snapshots to (its --snapshot-path); lets `snapshot` return the image path
+    #[arg(long)]
+    snapshot_dir: Option<PathBuf>,
+
+    /// Switch to this user once the listeners are bound, so privileged ports can be used
+    /// without serving as root (the controller must be started as root)
+    #[arg(long)]
+    run_as_user: Option<String>,
+
+    /// Group for --run-as-user (default: the user's primary group)
+    #[arg(long, requires = "run_as_user")]
+    run_as_group: Option<String>,
+
+    /// On SIGTERM or pi_quit, wait this long for in-flight commands before abandoning them and
+    /// exiting; keep it below systemd's TimeoutStopSec
+    #[arg(long, default_value_t = DEFAULT_SHUTDOWN_TIMEOUT_MS)]
+    shutdown_timeout_ms: u64,
+
+    /// Shut down gracefully once no command has been processed for this many seconds, e.g. when
+    /// started on demand by the service manager (0 = never)
+    #[arg(long, default_value_t = 0)]
+    idle_exit_secs: u64,
+
+    /// Directory load_playlist is confined to; relative playlist paths are taken from it
+    #[arg(long)]
+    playlist_dir: Option<PathBuf>,
+
+    /// Write the process ID to this file, removed again on graceful shutdown
+    #[arg(long)]
+    pid_file: Option<PathBuf>,
+
+    /// What to do if the PID file names a running process
+    #[arg(long, value_enum, default_value_t = PidFileConflict::Refuse)]
+    pid_file_conflict: PidFileConflict,
+
+    /// Serve HTTP on this address: Prometheus metrics at /metrics, the command list at /commands
+    #[arg(long)]
+    metrics_address: Option<String>,
+
+    /// Command run when VLC stops answering (program and arguments, no shell)
+    #[arg(long, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    on_vlc_down: Option<String>,
+
+    /// Command run when VLC answers again after --on-vlc-down, and at the first successful command
+    #[arg(long, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    on_vlc_up: Option<String>,
+
+    /// Command run when the controller shuts down, after in-flight commands have drained
+    #[arg(long, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    on_shutdown: Option<String>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -55,219 +384,2614 @@ impl LogLevel {
     }
 }
 
-const MAX_COMMAND_SIZE: usize = 128;
-const ALLOWED_COMMANDS: &[&str] = &[
-    "play", "pause", "stop", "next", "prev", "playlist", "frame", 
-    "pi_restart_vlc", "pi_shutdown", "pi_reboot"
-];
+/// When to colour the log output (`--color`).
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum LogColor {
+    /// When the log goes to a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+impl LogColor {
+    fn enabled(self) -> bool {
+        match self {
+            LogColor::Auto => std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            LogColor::Always => true,
+            LogColor::Never => false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum PrivilegeEscalation {
+    /// Prefix system commands with `sudo`
+    Sudo,
+    /// Prefix system commands with `doas`
+    Doas,
+    /// Run system commands directly (e.g. the service user has the capabilities)
+    None,
+}
+
+impl PrivilegeEscalation {
+    /// Binary used as the prefix, if any.
+    fn program(&self) -> Option<&'static str> {
+        match self {
+            PrivilegeEscalation::Sudo => Some("sudo"),
+            PrivilegeEscalation::Doas => Some("doas"),
+            PrivilegeEscalation::None => None,
+        }
+    }
+
+    /// Builds `args` as a command, prefixed with the escalation binary.
+    fn command(&self, args: &[&str]) -> Command {
+        match self.program() {
+            Some(program) => {
+                let mut command = Command::new(program);
+                command.args(args);
+                command
+            }
+            None => {
+                let mut command = Command::new(args[0]);
+                command.args(&args[1..]);
+                command
+            }
+        }
+    }
+
+    /// Binary that must be on PATH for the destructive system commands to run.
+    fn required_binary(&self) -> &'static str {
+        self.program().unwrap_or("shutdown")
+    }
+}
+
+/// Policy for commands not in the command list (`--unknown-command`).
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum UnknownCommand {
+    /// Pass them to VLC
+    Forward,
+    /// Answer ERR without contacting VLC
+    Reject,
+    /// Log a warning, then pass them to VLC
+    WarnAndForward,
+}
+
+/// Settings shared by all servers and command handlers.
+struct Controller {
+    vlc: Arc<dyn VlcTransport>,
+    privilege: PrivilegeEscalation,
+    mirror_addr: Option<String>,
+    max_line_length: usize,
+    max_command_size: usize,
+    query_cache: QueryCache,
+    coalescer: QueryCoalescer,
+    watchdog: Watchdog,
+    breaker: CircuitBreaker,
+    history: History,
+    /// `--completion-sink`
+    completion: Option<JsonLineSink>,
+    connection_events: ConnectionEvents,
+    /// Runtime log level control; `None` when no subscriber was installed (tests)
+    log_filter: Option<LogFilter>,
+    log_sampler: Sampler,
+    client_greeting: bool,
+    /// Queries take one permit, mutating commands take all of them
+    vlc_permits: Semaphore,
+    max_concurrent_queries: u32,
+    udp_concurrency: usize,
+    udp_source_ports: Option<PortRange>,
+    accept_proxy_protocol: bool,
+    tcp_auto_detect: bool,
+    trusted_proxies: Vec<access::Cidr>,
+    shutdown: Arc<Shutdown>,
+    mgmt_prefix: String,
+    disable_system_commands: bool,
+    read_only: bool,
+    /// `--command-prefix-filter`
+    command_prefix: Option<String>,
+    metrics: Metrics,
+    /// Volume to restore on `mute off`, set by `mute on`
+    saved_volume: Mutex<Option<u32>>,
+    stop_after_current: StopAfterCurrent,
+    loop_range: LoopRange,
+    /// Per-client allowlists from the config file; `None` allows everything
+    access: Option<AccessPolicy>,
+    /// `[response_transforms]` from the config file
+    transforms: ResponseTransforms,
+    /// `[aliases]` from the config file
+    aliases: Aliases,
+    unknown_command: UnknownCommand,
+    log_commands: bool,
+    echo_commands: bool,
+    snapshot_dir: Option<PathBuf>,
+    playlist_dir: Option<PathBuf>,
+    hooks: Hooks,
+}
+
+impl Controller {
+    fn new(
+        args: &Args,
+        vlc: Arc<dyn VlcTransport>,
+        access: Option<AccessPolicy>,
+        transforms: ResponseTransforms,
+        aliases: Aliases,
+        log_filter: Option<LogFilter>,
+    ) -> Self {
+        Self {
+            vlc,
+            privilege: args.privilege_escalation,
+            mirror_addr: args.mirror_address.clone(),
+            max_line_length: args.max_line_length,
+            max_command_size: args.max_command_size,
+            query_cache: QueryCache::new(Duration::from_millis(args.query_cache_ms)),
+            coalescer: QueryCoalescer::new(args.coalesce_queries),
+            watchdog: Watchdog::new(
+                args.watchdog_failures,
+                Duration::from_secs(args.watchdog_window_secs),
+                Duration::from_secs(args.watchdog_cooldown_secs),
+            ),
+            breaker: CircuitBreaker::new(args.circuit_failures, Duration::from_secs(args.circuit_cooldown_secs)),
+            history: History::new(),
+            completion: args.completion_sink.clone().map(JsonLineSink::new),
+            connection_events: ConnectionEvents::new(args.connection_events.clone()),
+            log_filter,
+            log_sampler: Sampler::new(args.log_sample_every),
+            client_greeting: args.client_greeting,
+            vlc_permits: Semaphore::new(args.max_concurrent_queries as usize),
+            max_concurrent_queries: args.max_concurrent_queries,
+            udp_concurrency: args.udp_concurrency as usize,
+            udp_source_ports: args.udp_source_ports,
+            accept_proxy_protocol: args.accept_proxy_protocol,
+            tcp_auto_detect: args.tcp_auto_detect,
+            trusted_proxies: args.trusted_proxies.clone(),
+            shutdown: Arc::new(Shutdown::new()),
+            mgmt_prefix: args.mgmt_prefix.clone(),
+            disable_system_commands: args.disable_system_commands,
+            read_only: args.read_only,
+            command_prefix: args.command_prefix_filter.clone(),
+            metrics: Metrics::default(),
+            saved_volume: Mutex::new(None),
+            stop_after_current: StopAfterCurrent::default(),
+            loop_range: LoopRange::default(),
+            access,
+            transforms,
+            aliases,
+            unknown_command: args.unknown_command,
+            log_commands: args.log_commands,
+            echo_commands: args.echo_commands,
+            snapshot_dir: args.snapshot_dir.clone(),
+            playlist_dir: args.playlist_dir.clone(),
+            hooks: Hooks::new(args.on_vlc_down.clone(), args.on_vlc_up.clone(), args.on_shutdown.clone()),
+        }
+    }
+
+    /// Forwards a command to VLC unless the circuit is open, feeding the outcome
+    /// to the watchdog and the circuit breaker.
+    ///
+    /// Read-only queries run concurrently (up to `max_concurrent_queries`);
+    /// anything else waits for exclusive access so mutations stay ordered.
+    async fn forward(&self, command: &[u8]) -> Result<String> {
+        let command = &vlc::command_line(command)?;
+        log_translation(command);
+        if !self.breaker.allow() {
+            debug!(reason_code = Rejection::VlcUnavailable.code(), "Circuit open, not contacting VLC");
+            anyhow::bail!(breaker::UNAVAILABLE);
+        }
+        let _permit = if is_query(command) {
+            self.vlc_permits.acquire().await?
+        } else {
+            self.exclusive_vlc_access().await?
+        };
+        let result = self.vlc.forward_with_retry(command).await;
+        match &result {
+            Ok(_) => {
+                self.watchdog.record_success();
+                self.breaker.record_success();
+                self.hooks.record_vlc(true);
+            }
+            Err(_) => {
+                self.hooks.record_vlc(false);
+                if self.breaker.record_failure() {
+                    let status = self.breaker.status();
+                    warn!(
+                        failures = status.consecutive_failures,
+                        cooldown_secs = status.secs_until_trial,
+                        "Circuit open: VLC considered down, failing commands fast"
+                    );
+                }
+                if self.watchdog.record_failure() {
+                    error!(
+                        threshold = self.watchdog.status().threshold,
+                        "Watchdog: VLC keeps failing, restarting it"
+                    );
+                    if let Err(e) = mgmt::restart_vlc().await {
+                        error!(error = %e, "Watchdog failed to restart VLC");
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Waits until every VLC command queued ahead of the caller has finished.
+    async fn exclusive_vlc_access(&self) -> Result<tokio::sync::SemaphorePermit<'_>> {
+        Ok(self.vlc_permits.acquire_many(self.max_concurrent_queries).await?)
+    }
+}
+
+tokio::task_local! {
+    /// The client command being processed, for `log_translation`.
+    static CLIENT_COMMAND: String;
+}
+
+/// Logs the raw command sent to VLC next to the client command it came from,
+/// when they differ, e.g. `seek_rel +30` becoming `seek 72`.
+fn log_translation(raw: &[u8]) {
+    let raw = String::from_utf8_lossy(raw);
+    let _ = CLIENT_COMMAND.try_with(|original| {
+        if original != raw.trim() {
+            debug!(command = %original, raw = %raw.trim(), "Translated command");
+        }
+    });
+}
+
+/// Returns true if `program` resolves to a file in one of the PATH directories.
+fn is_on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+/// Tracing target of the `--log-commands` log.
+const COMMAND_LOG_TARGET: &str = "vlc_control::command_log";
+
+/// Prefix of commands that are acknowledged before they run (`detach play`).
+const DETACH_VERB: &str = "detach";
+
+/// How long `--tcp-auto-detect` waits for a possible PROXY header from `--trusted-proxies`.
+const PROXY_SNIFF_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Largest UDP payload; a datagram may carry several newline-separated commands.
+const MAX_UDP_DATAGRAM: usize = 65_507;
+
+const DEFAULT_SHUTDOWN_TIMEOUT_MS: u64 = 5000;
+const SHUTDOWN_HOOK_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_LINE_LENGTH: usize = 128;
+const DEFAULT_MAX_COMMAND_SIZE: usize = 128;
+const DEFAULT_VLC_MAX_RESPONSE_BYTES: u64 = 1024 * 1024;
+/// Bounds both connecting to the mirror and waiting for its reply.
+const MIRROR_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_MGMT_PREFIX: &str = "pi_";
+/// Playback rates accepted by `rate_set`; `rate_up`/`rate_down` stop at the ends.
+const MIN_RATE: f64 = 0.25;
+const MAX_RATE: f64 = 4.0;
+const DEFAULT_RATE_STEP: f64 = 0.25;
+/// How long `swap_to` and `restart_item` wait for status to report playing, and how often they look.
+const PLAYBACK_START_TIMEOUT: Duration = Duration::from_secs(5);
+const PLAYBACK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// VLC's volume for 100%, on its 0-320 scale.
+const VLC_FULL_VOLUME: u32 = 256;
+
+/// True if the raw command is a read-only VLC query, see `commands::is_vlc_query`.
+fn is_query(command: &[u8]) -> bool {
+    std::str::from_utf8(command).is_ok_and(|command| commands::is_vlc_query(command.trim()))
+}
+
+/// Ingress path of a command, logged and used as the `transport` metrics label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Tcp,
+    Udp,
+    Fifo,
+}
+
+impl Transport {
+    const ALL: [Transport; 3] = [Transport::Tcp, Transport::Udp, Transport::Fifo];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Transport::Tcp => "tcp",
+            Transport::Udp => "udp",
+            Transport::Fifo => "fifo",
+        }
+    }
+}
+
+/// Why a command was refused before it reached VLC or the system.
+///
+/// The `code()` strings are logged as `reason_code` and must stay stable,
+/// since log pipelines alert on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rejection {
+    /// Frame exceeded `--max-line-length` or command exceeded `--max-command-size`
+    TooLarge,
+    /// Command bytes were not valid UTF-8
+    InvalidUtf8,
+    /// Command under the management prefix that is not a registered management command
+    UnauthorizedSystemCmd,
+    /// TCP connection without a valid PROXY header under `--accept-proxy-protocol`
+    MalformedProxyHeader,
+    /// Command outside the allowlist profile of the client
+    NotPermitted,
+    /// TLS handshake failed, e.g. no valid client certificate under `--tls-client-ca`
+    TlsHandshakeFailed,
+    /// Unrecognised command under `--unknown-command reject`
+    UnknownCommand,
+    /// VLC command while the circuit breaker is open
+    VlcUnavailable,
+    /// Command without the prefix required by `--command-prefix-filter`
+    MissingCommandPrefix,
+    /// Management command under `--disable-system-commands`
+    SystemCmdDisabled,
+    /// Mutating command under `--read-only`
+    ReadOnly,
+    /// Command with a CR or LF inside it, which VLC would read as a second command
+    EmbeddedLineBreak,
+}
+
+impl Rejection {
+    fn code(&self) -> &'static str {
+        match self {
+            Rejection::TooLarge => "too_large",
+            Rejection::InvalidUtf8 => "invalid_utf8",
+            Rejection::UnauthorizedSystemCmd => "unauthorized_system_cmd",
+            Rejection::MalformedProxyHeader => "malformed_proxy_header",
+            Rejection::NotPermitted => "not_permitted",
+            Rejection::TlsHandshakeFailed => "tls_handshake_failed",
+            Rejection::UnknownCommand => "unknown_command",
+            Rejection::VlcUnavailable => "vlc_unavailable",
+            Rejection::MissingCommandPrefix => "missing_command_prefix",
+            Rejection::SystemCmdDisabled => "system_cmd_disabled",
+            Rejection::ReadOnly => "read_only",
+            Rejection::EmbeddedLineBreak => "embedded_line_break",
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let args = parse_args()?;
+    if args.test_config {
+        let problems = validate::check(&args);
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        if !problems.is_empty() {
+            eprintln!("{} problem(s) found", problems.len());
+            std::process::exit(1);
+        }
+        println!("Configuration OK");
+        return Ok(());
+    }
     
     // Initialize structured logging with CLI argument or environment variable
-    let filter = if std::env::var("RUST_LOG").is_ok() {
-        // If RUST_LOG is set, use it (environment variable takes precedence)
-        EnvFilter::from_default_env()
-    } else {
-        // Otherwise, use the CLI argument
-        EnvFilter::new(format!("vlc_control={}", args.log_level.as_filter_str()))
-    };
-    
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .init();
+    let log_filter = logging::init(&args)?;
 
     info!(
-        vlc_addr = %args.vlc_address,
+        vlc_addr = %args.vlc_srv.as_ref().unwrap_or(&args.vlc_address),
         tcp_addr = %args.tcp_address, 
         udp_addr = %args.udp_address,
         "Starting VLC Controller servers..."
     );
+
+    if args.reuse_port && !listen::REUSE_PORT_SUPPORTED {
+        anyhow::bail!("--reuse-port is not supported on this platform");
+    }
+
+    // Held until main returns, which removes the file again.
+    let _pid_file = match &args.pid_file {
+        Some(path) => Some(PidFile::create(path, args.pid_file_conflict)?),
+        None => None,
+    };
+
+    let required = args.privilege_escalation.required_binary();
+    if args.disable_system_commands {
+        info!("System commands disabled, {}* commands will be refused", args.mgmt_prefix);
+    } else if !is_on_path(required) {
+        warn!(
+            binary = required,
+            "'{}' not found on PATH; {}shutdown and {}reboot will fail (see --privilege-escalation)",
+            required, args.mgmt_prefix, args.mgmt_prefix
+        );
+    }
+    if args.read_only {
+        info!("Read-only mode, commands that change playback or the system will be refused");
+    }
+    if args.vlc_password.is_some() && args.vlc_protocol != VlcProtocol::Http {
+        warn!("--vlc-password is only used with --vlc-protocol http, ignoring it");
+    }
+    if args.vlc_command.is_some() && args.vlc_protocol != VlcProtocol::Stdin {
+        warn!("--vlc-command is only used with --vlc-protocol stdin, ignoring it");
+    }
+    if args.vlc_protocol == VlcProtocol::Stdin && (args.vlc_address_fallback.is_some() || args.vlc_srv.is_some()) {
+        warn!("VLC is started by the controller with --vlc-protocol stdin, ignoring --vlc-address-fallback and --vlc-srv");
+    }
+    if let Some(dir) = &args.snapshot_dir {
+        snapshot::check_dir(dir)?;
+    }
+    if let Some(dir) = &args.playlist_dir {
+        playlist_file::check_dir(dir)?;
+    }
+    if let Some(path) = &args.fifo {
+        fifo::check(path)?;
+    }
     
-    // Clone addresses for the async tasks
-    let tcp_addr = args.tcp_address.clone();
-    let udp_addr = args.udp_address.clone();
-    let vlc_addr = args.vlc_address.clone();
+    let access = match &args.config {
+        Some(path) => AccessPolicy::from_config(config::load_access(path)?)?,
+        None => None,
+    };
+
+    let response_timeouts = match &args.config {
+        Some(path) => config::load_response_timeouts(path)?,
+        None => HashMap::new(),
+    };
+
+    let transforms = match &args.config {
+        Some(path) => config::load_response_transforms(path)?,
+        None => ResponseTransforms::default(),
+    };
+
+    let aliases = match &args.config {
+        Some(path) => config::load_aliases(path)?,
+        None => Aliases::default(),
+    };
+
+    let stdin = args.vlc_protocol == VlcProtocol::Stdin;
+    let vlc = VlcClient::new(
+        match &args.vlc_srv {
+            _ if stdin => "stdin".to_string(),
+            Some(srv) => srv.clone(),
+            None => args.vlc_address.clone(),
+        },
+        VlcOptions {
+            bind_addr: args.vlc_bind_address,
+            no_banner: args.vlc_no_banner,
+            protocol: args.vlc_protocol,
+            password: args.vlc_password.clone(),
+            command: args.vlc_command.clone(),
+            failure_log_interval: Duration::from_secs(args.vlc_error_log_interval_secs),
+            connect_timeout: args.vlc_connect_timeout_ms.map(Duration::from_millis),
+            fallback_addr: args.vlc_address_fallback.clone().filter(|_| !stdin),
+            retry_on: args.vlc_retry_on.clone(),
+            retry_budget: args.vlc_retry_budget.map(|retries| {
+                Arc::new(RetryBudget::new(retries, Duration::from_secs(args.vlc_retry_budget_window_secs)))
+            }),
+            response_timeout: args.vlc_response_timeout_ms.map(Duration::from_millis),
+            response_timeouts,
+            max_response_bytes: Some(args.vlc_max_response_bytes).filter(|&max| max > 0),
+            srv: args.vlc_srv.clone().filter(|_| !stdin).map(|name| Arc::new(SrvResolver::new(name))),
+        },
+    );
+    let controller = Arc::new(Controller::new(&args, Arc::new(vlc), access, transforms, aliases, Some(log_filter)));
+
+    let tls = tls_acceptor(&args)?;
+
+    // Bind everything while still privileged, then drop to --run-as-user.
+    let tcp_listener = listen::bind_tcp(&args.tcp_address, args.reuse_port).await?;
+    info!(address = %args.tcp_address, "TCP Server listening");
+    let udp_socket = listen::bind_udp(&args.udp_address, args.reuse_port).await?;
+    info!(address = %args.udp_address, "UDP Server listening");
+    let metrics_listener = match &args.metrics_address {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr).await?;
+            info!(address = %addr, "Metrics endpoint listening");
+            Some(listener)
+        }
+        None => None,
+    };
+    if let Some(user) = &args.run_as_user {
+        privileges::drop_to(user, args.run_as_group.as_deref())?;
+    }
+
+    tokio::spawn(poller::run(controller.clone()));
+
+    if let Some(path) = args.fifo.clone() {
+        let controller = controller.clone();
+        tokio::spawn(async move {
+            if let Err(e) = fifo::run(&path, controller).await {
+                error!(error = %e, "FIFO reader crashed");
+            }
+        });
+    }
+
+    if let Some(listener) = metrics_listener {
+        let controller = controller.clone();
+        tokio::spawn(async move {
+            if let Err(e) = web::run_server(listener, controller).await {
+                error!(error = %e, "Metrics endpoint crashed");
+            }
+        });
+    }
     
-    tokio::select! {
-        res = run_tcp_server(&tcp_addr, &vlc_addr) => {
-            if let Err(e) = res {
-                error!(error = %e, "TCP server crashed");
+    // Both sockets are bound above, so neither transport misses commands while
+    // the other starts. Each runs as its own task: one failing is logged and
+    // leaves the other serving.
+    let mut servers = JoinSet::new();
+    let tcp_server = run_tcp_server(tcp_listener, tls, controller.clone());
+    servers.spawn(async move { ("TCP", tcp_server.await) });
+    let udp_server = run_udp_server(udp_socket, controller.clone());
+    servers.spawn(async move { ("UDP", udp_server.await) });
+
+    // The servers keep running while this drains, so in-flight commands
+    // can finish; new ones are refused.
+    let drain = async {
+        tokio::select! {
+            _ = shutdown::signal() => info!("Shutdown requested, draining in-flight commands"),
+            _ = controller.shutdown.quit_requested() => {
+                info!("Quit requested by client, stopping the controller (the host stays up)");
             }
-        },
-        res = run_udp_server(&udp_addr, &vlc_addr) => {
-            if let Err(e) = res {
-                error!(error = %e, "UDP server crashed");
+            _ = idle_exit(&controller, args.idle_exit_secs) => {
+                info!(idle_secs = args.idle_exit_secs, "No commands for --idle-exit-secs, shutting down");
             }
-        },
+        }
+        controller.shutdown.drain(Duration::from_millis(args.shutdown_timeout_ms)).await
+    };
+    tokio::pin!(drain);
+
+    let stopped = loop {
+        tokio::select! {
+            Some(server) = servers.join_next() => {
+                match server {
+                    Ok((server, Err(e))) => error!(error = %e, "{} server crashed", server),
+                    Ok((server, Ok(()))) => warn!("{} server stopped", server),
+                    Err(e) => error!(error = %e, "Server task panicked"),
+                }
+                if servers.is_empty() {
+                    error!("No server left running, shutting down");
+                    break true;
+                }
+                warn!(remaining = servers.len(), "Still serving on the other transport");
+            }
+            report = &mut drain => {
+                if report.dropped > 0 {
+                    let abandoned: Vec<String> = report
+                        .abandoned
+                        .iter()
+                        .map(|(verb, client)| format!("{} from {}", verb, ClientAddr(*client)))
+                        .collect();
+                    warn!(
+                        processed = report.processed,
+                        dropped = report.dropped,
+                        abandoned = %abandoned.join(", "),
+                        timeout_ms = args.shutdown_timeout_ms,
+                        "Shutdown timed out, abandoning in-flight commands"
+                    );
+                } else {
+                    info!(processed = report.processed, "All in-flight commands finished, shutting down");
+                }
+                break false;
+            }
+        }
+    };
+    if let Some(hook) = controller.hooks.fire(HookEvent::Shutdown)
+        && tokio::time::timeout(SHUTDOWN_HOOK_TIMEOUT, hook).await.is_err()
+    {
+        warn!("Shutdown hook still running, exiting without waiting for it");
+    }
+    if stopped {
+        anyhow::bail!("TCP and UDP servers stopped");
     }
     Ok(())
 }
 
-/// TCP listener
-async fn run_tcp_server(tcp_addr: &str, vlc_addr: &str) -> Result<()> {
-    let listener = TcpListener::bind(tcp_addr).await?;
-    info!(address = tcp_addr, "TCP Server listening");
-    
-    let vlc_addr = vlc_addr.to_string(); // Clone for use in spawned tasks
+/// The TLS acceptor for `--tls-cert` and `--tls-key`, if given.
+fn tls_acceptor(args: &Args) -> Result<Option<TlsAcceptor>> {
+    Ok(match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(cert, key, args.tls_client_ca.as_deref())?),
+        (None, None) if args.tls_client_ca.is_some() => anyhow::bail!("--tls-client-ca requires --tls-cert and --tls-key"),
+        (None, None) => None,
+        _ => anyhow::bail!("--tls-cert and --tls-key must be given together"),
+    })
+}
+
+/// Parses the command line, layered over the `--config` file if one is given.
+fn parse_args() -> Result<Args> {
+    let cli: Vec<OsString> = std::env::args_os().collect();
+    let args = Args::parse_from(&cli);
+    let Some(path) = &args.config else {
+        return Ok(args);
+    };
+
+    // Settings from the file go first so that repeated command-line options override them.
+    let mut merged = cli[..1].to_vec();
+    merged.extend(config::load_args(path)?);
+    merged.extend_from_slice(&cli[1..]);
+    Ok(Args::parse_from(merged))
+}
+
+/// Resolves after `--idle-exit-secs` without commands; never when it is 0.
+async fn idle_exit(controller: &Controller, idle_secs: u64) {
+    if idle_secs == 0 {
+        return std::future::pending().await;
+    }
+    controller.shutdown.idle_for(Duration::from_secs(idle_secs)).await
+}
 
+/// TCP listener
+async fn run_tcp_server(listener: TcpListener, tls: Option<TlsAcceptor>, controller: Arc<Controller>) -> Result<()> {
     loop {
         // Accept a new connection.
         let (socket, addr) = listener.accept().await?;
-        info!(client_addr = %addr, "Got inbound TCP connection");
+        info!(client_addr = %ClientAddr(addr), "Got inbound TCP connection");
 
         // Spawn a new asynchronous task
-        let vlc_addr_clone = vlc_addr.clone();
+        let controller = controller.clone();
+        let tls = tls.clone();
+        controller.metrics.tcp_connection_opened();
         tokio::spawn(async move {
-            if let Err(e) = handle_tcp_connection(socket, &vlc_addr_clone).await {
-                error!(client_addr = %addr, error = %e, "Error handling TCP client");
+            let started = Instant::now();
+            if let Err(e) = handle_tcp_connection(socket, addr, tls, &controller).await {
+                error!(client_addr = %ClientAddr(addr), error = %e, "Error handling TCP client");
             }
+            controller.metrics.tcp_connection_closed(started.elapsed());
         });
     }
 }
 
-/// Handles a TCP client connection 
-async fn handle_tcp_connection(mut socket: TcpStream, vlc_addr: &str) -> Result<()> {
-    // Split the socket into separate reader and writer halves.
-    let (reader, _writer) = socket.split();
-
-    // BufReader now takes ownership of the `reader` half only.
-    let mut buf_reader = BufReader::new(reader);
-    let mut line = String::new();
-
-    // Read lines from the client in a loop.
-    while buf_reader.read_line(&mut line).await? != 0 {
-        let command = line.trim();
-        debug!(command = %command, "Received TCP message");
-        process_command(line.as_bytes(), vlc_addr).await?;
+/// Handles a TCP client connection: PROXY header, TLS handshake, then commands.
+async fn handle_tcp_connection(
+    mut socket: TcpStream,
+    mut addr: SocketAddr,
+    tls: Option<TlsAcceptor>,
+    controller: &Arc<Controller>,
+) -> Result<()> {
+    if controller.accept_proxy_protocol || (is_trusted_proxy(addr, controller) && starts_with_proxy_header(&socket).await?) {
+        let mut line = Vec::new();
+        let header = match read_proxy_line(&mut socket, &mut line).await? {
+            Frame::Eof => return Ok(()),
+            Frame::Oversized | Frame::CommandTooLarge => Err(anyhow::anyhow!("PROXY header too long")),
+            Frame::Line => proxy::parse_v1(&line),
+        };
+        match header {
+            Ok(Some(client_addr)) => {
+                info!(proxy_addr = %addr, client_addr = %ClientAddr(client_addr), "Accepted PROXY header");
+                addr = client_addr;
+            }
+            Ok(None) => debug!(proxy_addr = %addr, "PROXY header without client address"),
+            Err(e) => {
+                warn!(
+                    reason_code = Rejection::MalformedProxyHeader.code(),
+                    proxy_addr = %addr,
+                    error = %e,
+                    "Rejected connection with malformed PROXY header"
+                );
+                record_rejection(controller, Transport::Tcp, addr, Rejection::MalformedProxyHeader);
+                return Ok(());
+            }
+        }
+    }
 
-        line.clear(); // Clear the buffer for the next line.
+    let Some(tls) = tls else {
+        return serve_and_report(socket, addr, controller).await;
+    };
+    let stream = match tls.accept(socket).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!(
+                reason_code = Rejection::TlsHandshakeFailed.code(),
+                client_addr = %ClientAddr(addr),
+                error = %e,
+                "Rejected connection, TLS handshake failed"
+            );
+            record_rejection(controller, Transport::Tcp, addr, Rejection::TlsHandshakeFailed);
+            return Ok(());
+        }
+    };
+    let peer_names = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .map(tls::cert_names)
+        .unwrap_or_default();
+    if !peer_names.is_empty() {
+        info!(client_addr = %ClientAddr(addr), cert_names = ?peer_names, "TLS client authenticated");
     }
-    
-    info!("TCP client disconnected cleanly");
-    Ok(())
+    tls::with_peer_names(peer_names, serve_and_report(stream, addr, controller)).await
 }
 
-/// UDP listener
-async fn run_udp_server(udp_addr: &str, vlc_addr: &str) -> Result<()> {
-    let socket = UdpSocket::bind(udp_addr).await?;
-    info!(address = udp_addr, "UDP Server listening");
-    let mut buf = [0; 1024];
+/// Serves an accepted client, reporting the connection to `--connection-events`.
+async fn serve_and_report<S>(stream: S, addr: SocketAddr, controller: &Arc<Controller>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let started = Instant::now();
+    controller.connection_events.opened(Transport::Tcp, addr);
+    let result = serve_commands(stream, addr, controller).await;
+    controller.connection_events.closed(Transport::Tcp, addr, started.elapsed(), result.as_ref().err());
+    result
+}
 
-    loop {
-        let (len, addr) = socket.recv_from(&mut buf).await?;
-        let command = String::from_utf8_lossy(&buf[..len]);
-        debug!(client_addr = %addr, command = %command.trim(), "Got UDP datagram");
-        process_command(&buf[..len], vlc_addr).await?;
-    }
+/// Counts a refused connection or command and reports it to `--connection-events`.
+fn record_rejection(controller: &Controller, transport: Transport, addr: SocketAddr, rejection: Rejection) {
+    controller.metrics.rejected(rejection.code());
+    controller.connection_events.rejected(transport, addr, rejection.code());
 }
 
-/// Command dispatcher
-async fn process_command(data: &[u8], vlc_addr: &str) -> Result<()> {
-    // Size validation
-    if data.len() > MAX_COMMAND_SIZE {
-        anyhow::bail!("Command too large: {} bytes (max {})", data.len(), MAX_COMMAND_SIZE);
-    }
-    // convert byte slice to string
-    let command = std::str::from_utf8(data)?.trim();
-    // Validate the command
-    if command.starts_with("pi_") && !ALLOWED_COMMANDS.contains(&command) {
-        warn!(command = %command, "Blocked unauthorized system command");
-        anyhow::bail!("Unauthorized system command: {}", command);
-    }
+/// `--tcp-auto-detect`: whether `addr` is one of `--trusted-proxies`, the
+/// only peers whose connections are sniffed for a PROXY header. Anyone else
+/// could claim any client address in one.
+fn is_trusted_proxy(addr: SocketAddr, controller: &Controller) -> bool {
+    controller.tcp_auto_detect && controller.trusted_proxies.iter().any(|proxy| proxy.contains(addr))
+}
 
-    match command {
-        "pi_restart_vlc" => {
-            info!("Executing VLC restart command");
-            let status = Command::new("systemctl")
-                .args(["--user", "restart", "vlc-loader.service"])
-                .status()?; // .status() waits for the command to finish.
-            if status.success() {
-                info!("VLC restart command completed successfully");
-            } else {
-                warn!(exit_code = status.code(), "VLC restart command failed");
-            }
-        }
-        "pi_shutdown" => {
-            warn!("Executing system shutdown command");
-            let status = Command::new("sudo").args(["shutdown", "-h", "now"]).status()?;
-            if status.success() {
-                info!("Shutdown command completed successfully");
-            } else {
-                error!(exit_code = status.code(), "Shutdown command failed");
-            }
-        }
-        "pi_reboot" => {
-            warn!("Executing system reboot command");
-            let status = Command::new("sudo").args(["shutdown", "-r", "now"]).status()?;
-            if status.success() {
-                info!("Reboot command completed successfully");
-            } else {
-                error!(exit_code = status.code(), "Reboot command failed");
+/// `--tcp-auto-detect`: whether the connection opens with a PROXY header.
+///
+/// Proxies send the header as soon as they connect; a client that sends
+/// nothing for `PROXY_SNIFF_TIMEOUT` (say, one waiting for the greeting) is
+/// taken to be talking to us directly.
+async fn starts_with_proxy_header(socket: &TcpStream) -> Result<bool> {
+    const SIGNATURE: &[u8] = b"PROXY ";
+    let mut buf = [0; SIGNATURE.len()];
+    let sniff = async {
+        loop {
+            let len = socket.peek(&mut buf).await?;
+            if len == 0 || !SIGNATURE.starts_with(&buf[..len]) {
+                return Ok(false);
             }
+            if len == SIGNATURE.len() {
+                return Ok(true);
+            }
+            // Only part of the signature has arrived; peeking again at once would spin.
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
-        _ => {
-            // Assume it's a command for VLC.
-            debug!(command = %command, "Forwarding command to VLC");
-            forward_to_vlc_with_retry(data, vlc_addr).await?;
-        }
-    }
-    Ok(())
+    };
+    tokio::time::timeout(PROXY_SNIFF_TIMEOUT, sniff).await.unwrap_or(Ok(false))
 }
 
-// 3 attempts to connect to vlc then error
-async fn forward_to_vlc_with_retry(command: &[u8], vlc_addr: &str) -> Result<()> {
-    let max_retries = 3;
-    let mut retry_delay = Duration::from_millis(100);
-    
-    for attempt in 1..=max_retries {
-        match forward_to_vlc(command, vlc_addr).await {
-            Ok(response) => return Ok(response),
-            Err(e) if attempt < max_retries => {
-                warn!(
-                    attempt = attempt,
-                    error = %e,
-                    delay_ms = retry_delay.as_millis(),
-                    "VLC connection failed, retrying..."
-                );
-                tokio::time::sleep(retry_delay).await;
-                retry_delay *= 2;
-            }
-            Err(e) => {
-                error!(attempts = max_retries, error = %e, "VLC connection failed permanently");
-                return Err(e);
+/// Reads the PROXY header one byte at a time, so that nothing after it (such
+/// as the TLS handshake) is consumed.
+async fn read_proxy_line(socket: &mut TcpStream, buf: &mut Vec<u8>) -> Result<Frame> {
+    loop {
+        let byte = match socket.read_u8().await {
+            Ok(byte) => byte,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Ok(if buf.is_empty() { Frame::Eof } else { Frame::Line });
             }
+            Err(e) => return Err(e.into()),
+        };
+        buf.push(byte);
+        if byte == b'\n' {
+            return Ok(Frame::Line);
+        }
+        if buf.len() > proxy::MAX_HEADER_LEN {
+            return Ok(Frame::Oversized);
         }
     }
-    unreachable!()
 }
 
-/// Connects to VLC to forward a command.
-async fn forward_to_vlc(command: &[u8], vlc_addr: &str) -> Result<()> {
-    // Make the stream mutable so the reader can borrow it.
-    let mut stream = TcpStream::connect(vlc_addr).await?;
-    debug!(address = vlc_addr, "Connected to VLC");
+/// Reads and answers newline-delimited commands until the client disconnects.
+async fn serve_commands<S>(stream: S, addr: SocketAddr, controller: &Arc<Controller>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Split the stream into separate reader and writer halves.
+    let (reader, mut writer) = tokio::io::split(stream);
 
-    // The BufReader takes a *mutable* borrow of the stream.
-    let mut reader = BufReader::new(&mut stream);
-    let mut response_buf = Vec::new();
+    if controller.client_greeting {
+        writer.write_all(format!("vlc-control {}\n", env!("CARGO_PKG_VERSION")).as_bytes()).await?;
+    }
 
-    // Read the initial prompt
-    reader.read_until(b'>', &mut response_buf).await?;
-    debug!("Read VLC initial prompt");
-    
-    // To write, get a mutable reference to the underlying
-    // stream directly from the reader itself.
-    reader.get_mut().write_all(command).await?;
-    debug!(command = %String::from_utf8_lossy(command).trim(), "Sent command to VLC");
+    // BufReader now takes ownership of the `reader` half only.
+    let mut buf_reader = BufReader::new(reader);
+    let mut line = Vec::new();
 
-    // Clear the buffer and continue using the same reader for the reply.
-    response_buf.clear();
-    reader.read_until(b'>', &mut response_buf).await?;
+    // Errors are left for the first read below to report.
+    let json = controller.tcp_auto_detect && matches!(buf_reader.fill_buf().await, Ok([b'{', ..]));
+    if json {
+        debug!(client_addr = %ClientAddr(addr), "TCP client speaks JSON lines");
+    }
+    // Replaced by `logging::verbose_span` if the first line is `#verbose`.
+    let mut span = Span::none();
+    let mut first = true;
 
-    let response = String::from_utf8_lossy(&response_buf);
-    debug!(response = %response.trim(), "VLC response received\n");
+    // Read lines from the client in a loop.
+    loop {
+        let frame = match read_command(&mut buf_reader, &mut line, controller).await {
+            Ok(frame) => frame,
+            // TLS clients often close without close_notify; that is still a disconnect.
+            Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::UnexpectedEof) => {
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        if std::mem::take(&mut first) && !json && matches!(frame, Frame::Line) && line.trim_ascii() == logging::VERBOSE_DIRECTIVE {
+            info!(client_addr = %ClientAddr(addr), "Logging this connection's commands at debug level");
+            span = logging::verbose_span(addr);
+            writer.write_all(format_reply(&Ok(String::new())).as_bytes()).await?;
+            line.clear();
+            continue;
+        }
+        let mut id = serde_json::Value::Null;
+        let result = match frame {
+            Frame::Eof => break,
+            Frame::Oversized => Err(reject_oversized_frame(line.len(), controller.max_line_length)),
+            Frame::CommandTooLarge => Err(reject_joined_command(controller.max_command_size)),
+            Frame::Line if json => match jsonl::parse(&line) {
+                Ok(request) => {
+                    id = request.id;
+                    debug!(command = %request.command, "Received TCP JSON request");
+                    log_received_command(Transport::Tcp, addr, request.command.as_bytes(), controller);
+                    process_command(request.command.as_bytes(), addr, Transport::Tcp, controller).await
+                }
+                Err(e) => Err(e),
+            },
+            Frame::Line => {
+                if controller.echo_commands {
+                    writer.write_all(echo_line(&line).as_bytes()).await?;
+                }
+                let command = async {
+                    debug!(command = %String::from_utf8_lossy(&line).trim(), "Received TCP message");
+                    log_received_command(Transport::Tcp, addr, &line, controller);
+                    process_command(&line, addr, Transport::Tcp, controller).await
+                };
+                command.instrument(span.clone()).await
+            }
+        };
+        let reply = if json { jsonl::reply(&id, &result) } else { format_reply(&result) };
+        writer.write_all(reply.as_bytes()).await?;
 
-    Ok(())
+        line.clear(); // Clear the buffer for the next line.
+    }
+    
+    info!("TCP client disconnected cleanly");
+    Ok(())
+}
+
+/// Outcome of reading one line-delimited frame from a client.
+enum Frame {
+    Eof,
+    Line,
+    /// Longer than the frame limit; the rest of the line has been discarded.
+    Oversized,
+    /// Continuation lines joined past `--max-command-size`; the rest of the
+    /// command has been discarded.
+    CommandTooLarge,
+}
+
+/// Reads one TCP command into `buf`. A line ending in `\` continues on the
+/// next one: the backslash and line break are dropped and the lines joined.
+async fn read_command<R: AsyncBufRead + Unpin>(reader: &mut R, buf: &mut Vec<u8>, controller: &Controller) -> Result<Frame> {
+    let frame = read_frame(reader, buf, controller.max_line_length).await?;
+    if !matches!(frame, Frame::Line) {
+        return Ok(frame);
+    }
+    let mut continued = strip_continuation(buf);
+    let mut too_large = false;
+    let mut line = Vec::new();
+    while continued {
+        line.clear();
+        match read_frame(reader, &mut line, controller.max_line_length).await? {
+            Frame::Line => {}
+            Frame::Eof => break,
+            frame => {
+                std::mem::swap(buf, &mut line);
+                return Ok(frame);
+            }
+        }
+        continued = strip_continuation(&mut line);
+        // Keep reading past the limit so the rest of the command is not taken for new ones.
+        too_large |= buf.len() + line.len() > controller.max_command_size;
+        if too_large {
+            buf.clear();
+        } else {
+            buf.extend_from_slice(&line);
+        }
+    }
+    Ok(if too_large { Frame::CommandTooLarge } else { Frame::Line })
+}
+
+/// Drops a trailing `\` (and the line break after it), returning whether there was one.
+fn strip_continuation(line: &mut Vec<u8>) -> bool {
+    let Some(joined) = line.trim_ascii_end().strip_suffix(b"\\") else {
+        return false;
+    };
+    let len = joined.len();
+    line.truncate(len);
+    true
+}
+
+/// Reads one newline-terminated frame into `buf`, never buffering more than
+/// `max_len + 1` bytes of it.
+async fn read_frame<R: AsyncBufRead + Unpin>(reader: &mut R, buf: &mut Vec<u8>, max_len: usize) -> Result<Frame> {
+    let read = reader.take(max_len as u64 + 1).read_until(b'\n', buf).await?;
+    if read == 0 {
+        return Ok(Frame::Eof);
+    }
+    if read <= max_len {
+        return Ok(Frame::Line);
+    }
+
+    // Skip the remainder of the oversized line so the next frame starts cleanly.
+    if buf.last() != Some(&b'\n') {
+        loop {
+            let available = reader.fill_buf().await?;
+            if available.is_empty() {
+                break;
+            }
+            match available.iter().position(|&b| b == b'\n') {
+                Some(end) => {
+                    reader.consume(end + 1);
+                    break;
+                }
+                None => {
+                    let len = available.len();
+                    reader.consume(len);
+                }
+            }
+        }
+    }
+    Ok(Frame::Oversized)
+}
+
+/// Logs and builds the error for a frame over `--max-line-length`.
+fn reject_oversized_frame(size: usize, max: usize) -> anyhow::Error {
+    warn!(reason_code = Rejection::TooLarge.code(), size = size, max = max, "Rejected oversized frame");
+    anyhow::anyhow!("Frame too large: more than {} bytes", max)
+}
+
+fn reject_joined_command(max: usize) -> anyhow::Error {
+    warn!(reason_code = Rejection::TooLarge.code(), max = max, "Rejected oversized continued command");
+    anyhow::anyhow!("Command too large: more than {} bytes", max)
+}
+
+/// UDP listener. Datagrams are handled on tasks, at most `--udp-concurrency`
+/// at once, so a slow VLC does not stop the socket from being drained; the
+/// datagrams of one source are still handled one after another, in order.
+async fn run_udp_server(socket: UdpSocket, controller: Arc<Controller>) -> Result<()> {
+    let socket = Arc::new(socket);
+    let permits = Arc::new(Semaphore::new(controller.udp_concurrency));
+    let queues: Arc<Mutex<HashMap<SocketAddr, UdpQueue>>> = Arc::default();
+    let mut buf = vec![0; MAX_UDP_DATAGRAM];
+
+    loop {
+        // Received datagrams wait in the kernel buffer while every permit is taken.
+        let permit = permits.clone().acquire_owned().await?;
+        let (len, addr) = socket.recv_from(&mut buf).await?;
+        if let Some(ports) = &controller.udp_source_ports
+            && !ports.contains(addr.port())
+        {
+            debug!(client_addr = %ClientAddr(addr), allowed_ports = %ports, "Dropped UDP datagram from a source port outside --udp-source-ports");
+            continue;
+        }
+        debug!(client_addr = %ClientAddr(addr), command = %String::from_utf8_lossy(&buf[..len]).trim(), "Got UDP datagram");
+
+        let mut datagram = (buf[..len].to_vec(), permit);
+        let mut queues_guard = queues.lock().unwrap();
+        if let Some(queue) = queues_guard.get(&addr) {
+            match queue.send(datagram) {
+                Ok(()) => continue,
+                Err(unsent) => datagram = unsent.0,
+            }
+        }
+        let (queue, datagrams) = tokio::sync::mpsc::unbounded_channel();
+        let _ = queue.send(datagram);
+        queues_guard.insert(addr, queue);
+        drop(queues_guard);
+        tokio::spawn(serve_udp_source(addr, datagrams, socket.clone(), queues.clone(), controller.clone()));
+    }
+}
+
+/// Datagrams from one source waiting to be handled, each holding its permit.
+type UdpQueue = tokio::sync::mpsc::UnboundedSender<(Vec<u8>, OwnedSemaphorePermit)>;
+
+/// Handles the queued datagrams of `addr` in order, ending once the queue is empty.
+async fn serve_udp_source(
+    addr: SocketAddr,
+    mut datagrams: tokio::sync::mpsc::UnboundedReceiver<(Vec<u8>, OwnedSemaphorePermit)>,
+    socket: Arc<UdpSocket>,
+    queues: Arc<Mutex<HashMap<SocketAddr, UdpQueue>>>,
+    controller: Arc<Controller>,
+) {
+    loop {
+        // Checked under the lock the receive loop queues under, so no datagram
+        // is queued after the queue has been retired.
+        let next = {
+            let mut queues = queues.lock().unwrap();
+            let next = datagrams.try_recv().ok();
+            if next.is_none() {
+                queues.remove(&addr);
+            }
+            next
+        };
+        let Some((datagram, _permit)) = next else {
+            return;
+        };
+        let replies = udp_replies(&datagram, addr, &controller).await;
+        if !replies.is_empty()
+            && let Err(e) = socket.send_to(replies.as_bytes(), addr).await
+        {
+            warn!(client_addr = %ClientAddr(addr), error = %e, "Failed to send UDP reply");
+        }
+    }
+}
+
+/// Processes each line of a datagram as a command, as on TCP, and returns
+/// the replies together in order. Blank lines get no reply.
+async fn udp_replies(datagram: &[u8], addr: SocketAddr, controller: &Arc<Controller>) -> String {
+    let mut replies = String::new();
+    for line in datagram.split_inclusive(|&b| b == b'\n') {
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        let result = if line.len() > controller.max_line_length {
+            Err(reject_oversized_frame(line.len(), controller.max_line_length))
+        } else {
+            if controller.echo_commands {
+                replies.push_str(&echo_line(line));
+            }
+            log_received_command(Transport::Udp, addr, line, controller);
+            process_command(line, addr, Transport::Udp, controller).await
+        };
+        if let Err(e) = &result {
+            debug!(client_addr = %ClientAddr(addr), error = %e, "UDP command failed");
+        }
+        replies.push_str(&format_reply(&result));
+    }
+    replies
+}
+
+/// Formats a command outcome as the reply sent back to the client:
+/// `OK`, `OK <response>` or `ERR <reason>`, newline terminated.
+fn format_reply(result: &Result<String>) -> String {
+    match result {
+        Ok(response) if response.is_empty() => "OK\n".to_string(),
+        Ok(response) => format!("OK {}\n", response),
+        Err(e) => format!("ERR {}\n", e),
+    }
+}
+
+/// `--echo-commands`: `ECHO <command>` as the controller reads the frame,
+/// with control characters escaped so they cannot break the line framing.
+fn echo_line(data: &[u8]) -> String {
+    let text = String::from_utf8_lossy(data);
+    let mut echo = String::from("ECHO ");
+    for c in text.trim().chars() {
+        if c.is_control() {
+            echo.extend(c.escape_default());
+        } else {
+            echo.push(c);
+        }
+    }
+    echo.push('\n');
+    echo
+}
+
+/// `--log-commands`: logs a received command at info level. Arguments of
+/// management commands are redacted.
+fn log_received_command(transport: Transport, client_addr: SocketAddr, data: &[u8], controller: &Controller) {
+    if !controller.log_commands {
+        return;
+    }
+    let text = String::from_utf8_lossy(data);
+    let text = text.trim();
+    let command = match text.split_once(char::is_whitespace) {
+        Some((verb, _)) if verb.starts_with(controller.mgmt_prefix.as_str()) => format!("{} <redacted>", verb),
+        _ => text.to_string(),
+    };
+    info!(
+        target: COMMAND_LOG_TARGET,
+        transport = transport.as_str(),
+        client_addr = %ClientAddr(client_addr),
+        command = %command,
+        "Received command"
+    );
+}
+
+/// Processes one command from `client_addr`, counting it under `transport`.
+async fn process_command(
+    data: &[u8],
+    client_addr: SocketAddr,
+    transport: Transport,
+    controller: &Arc<Controller>,
+) -> Result<String> {
+    let result = controller.log_sampler.scope(handle_command(data, client_addr, controller)).await;
+    if let Err(e) = &result
+        && e.is::<access::NotPermitted>()
+    {
+        record_rejection(controller, transport, client_addr, Rejection::NotPermitted);
+    }
+    controller.metrics.command_processed(transport, result.is_ok());
+    result
+}
+
+/// Runs one command and records it in the history.
+async fn handle_command(data: &[u8], client_addr: SocketAddr, controller: &Arc<Controller>) -> Result<String> {
+    let command = match strip_command_prefix(data, client_addr, controller).and_then(|data| expand_alias(data, controller)) {
+        Ok(command) => command,
+        Err(e) => {
+            let result = Err(e);
+            record_history(data, client_addr, &result, controller);
+            return result;
+        }
+    };
+    let data = &*command;
+    if let Some(command) = data.strip_prefix(DETACH_VERB.as_bytes())
+        && command.first().is_none_or(u8::is_ascii_whitespace)
+    {
+        return detach_command(command, client_addr, controller);
+    }
+
+    let original = String::from_utf8_lossy(data).trim().to_string();
+    let result = match controller.shutdown.begin(&original, client_addr) {
+        Some(_in_flight) => {
+            CLIENT_COMMAND.scope(original, dispatch_command(data, client_addr, controller)).await
+        }
+        None => Err(anyhow::anyhow!("Shutting down, command not accepted")),
+    };
+    record_history(data, client_addr, &result, controller);
+    result
+}
+
+/// The command an `[aliases]` entry stands for, or `data` itself if it is no alias.
+fn expand_alias<'a>(data: &'a [u8], controller: &Controller) -> Result<Cow<'a, [u8]>> {
+    // Invalid UTF-8 is left for the dispatcher to reject.
+    let Ok(command) = std::str::from_utf8(data) else {
+        return Ok(Cow::Borrowed(data));
+    };
+    match controller.aliases.expand(command)? {
+        Some(expanded) => {
+            debug!(alias = %command.trim(), command = %expanded, "Expanded alias");
+            Ok(Cow::Owned(format!("{}\n", expanded).into_bytes()))
+        }
+        None => Ok(Cow::Borrowed(data)),
+    }
+}
+
+/// Removes the `--command-prefix-filter` prefix, failing if the command lacks it.
+fn strip_command_prefix<'a>(data: &'a [u8], client_addr: SocketAddr, controller: &Controller) -> Result<&'a [u8]> {
+    let Some(prefix) = &controller.command_prefix else {
+        return Ok(data);
+    };
+    match data.trim_ascii_start().strip_prefix(prefix.as_bytes()) {
+        Some(command) => Ok(command),
+        None => {
+            warn!(
+                reason_code = Rejection::MissingCommandPrefix.code(),
+                client_addr = %ClientAddr(client_addr),
+                command = %String::from_utf8_lossy(data).trim(),
+                "Rejected command without the required prefix"
+            );
+            anyhow::bail!("Missing command prefix '{}'", prefix);
+        }
+    }
+}
+
+/// Acknowledges `detach <command>` at once and runs the command in the
+/// background, so it completes even if the client disconnects. It stays in
+/// flight for graceful shutdown and is recorded in the history when done.
+fn detach_command(data: &[u8], client_addr: SocketAddr, controller: &Arc<Controller>) -> Result<String> {
+    if data.trim_ascii().is_empty() {
+        anyhow::bail!("Invalid detach: expected 'detach <command>'");
+    }
+    // Expanded now so a wrong argument count is reported instead of acknowledged.
+    let data = expand_alias(data.trim_ascii_start(), controller)?.into_owned();
+    check_line_breaks(String::from_utf8_lossy(&data).trim())?;
+    check_read_only(String::from_utf8_lossy(&data).trim(), controller)?;
+    let Some(in_flight) = controller.shutdown.begin_owned(&String::from_utf8_lossy(&data), client_addr) else {
+        anyhow::bail!("Shutting down, command not accepted");
+    };
+
+    let controller = controller.clone();
+    let peer_names = tls::peer_names();
+    tokio::spawn(async move {
+        let _in_flight = in_flight;
+        let original = String::from_utf8_lossy(&data).trim().to_string();
+        let dispatch = CLIENT_COMMAND.scope(original.clone(), dispatch_command(&data, client_addr, &controller));
+        let result = tls::with_peer_names(peer_names, dispatch).await;
+        match &result {
+            Ok(_) => debug!(client_addr = %ClientAddr(client_addr), command = %original, "Detached command finished"),
+            Err(e) => warn!(client_addr = %ClientAddr(client_addr), command = %original, error = %e, "Detached command failed"),
+        }
+        record_history(&data, client_addr, &result, &controller);
+    });
+    Ok("detached".to_string())
+}
+
+/// Refuses a command with a line break inside it. It is checked and
+/// classified as one command, but VLC would run each line separately.
+fn check_line_breaks(command: &str) -> Result<()> {
+    if command.contains(['\r', '\n']) {
+        warn!(
+            reason_code = Rejection::EmbeddedLineBreak.code(),
+            command = %command.escape_debug(),
+            "Rejected command with an embedded line break"
+        );
+        anyhow::bail!("Command contains a line break");
+    }
+    Ok(())
+}
+
+/// Refuses a mutating command under `--read-only`.
+fn check_read_only(command: &str, controller: &Controller) -> Result<()> {
+    if controller.read_only && commands::is_mutating(command, &controller.mgmt_prefix) {
+        warn!(reason_code = Rejection::ReadOnly.code(), command = %command, "Refused mutating command in read-only mode");
+        anyhow::bail!("read_only");
+    }
+    Ok(())
+}
+
+fn record_history(data: &[u8], client_addr: SocketAddr, result: &Result<String>, controller: &Controller) {
+    let text = String::from_utf8_lossy(data);
+    let verb: String = text.split_whitespace().next().unwrap_or_default().chars().take(32).collect();
+    let error = result.as_ref().err().map(|e| e.to_string());
+    let entry = controller.history.record(&verb, client_addr, error);
+    if let Some(sink) = &controller.completion {
+        sink.emit(&entry);
+    }
+}
+
+/// Command dispatcher, returns the response to relay to the client.
+async fn dispatch_command(data: &[u8], client_addr: SocketAddr, controller: &Controller) -> Result<String> {
+    // Size validation
+    if data.len() > controller.max_command_size {
+        warn!(
+            reason_code = Rejection::TooLarge.code(),
+            size = data.len(),
+            max = controller.max_command_size,
+            "Rejected oversized command"
+        );
+        anyhow::bail!("Command too large: {} bytes (max {})", data.len(), controller.max_command_size);
+    }
+    // convert byte slice to string
+    let command = match std::str::from_utf8(data) {
+        Ok(command) => command.trim(),
+        Err(e) => {
+            warn!(reason_code = Rejection::InvalidUtf8.code(), error = %e, "Rejected non-UTF-8 command");
+            return Err(e.into());
+        }
+    };
+    check_line_breaks(command)?;
+    let (verb, args) = command
+        .split_once(char::is_whitespace)
+        .map(|(verb, args)| (verb, args.trim()))
+        .unwrap_or((command, ""));
+
+    if let Some(access) = &controller.access
+        && !commands::UNRESTRICTED_COMMANDS.contains(&verb)
+        && let Err(e) = access.check(client_addr, &tls::peer_names(), verb)
+    {
+        warn!(
+            reason_code = Rejection::NotPermitted.code(),
+            client_addr = %ClientAddr(client_addr),
+            command = %command,
+            "Rejected command outside client profile"
+        );
+        return Err(e);
+    }
+    check_read_only(command, controller)?;
+
+    // Management commands are only reachable through the registry.
+    if let Some(name) = verb.strip_prefix(controller.mgmt_prefix.as_str()) {
+        if controller.disable_system_commands {
+            warn!(
+                reason_code = Rejection::SystemCmdDisabled.code(),
+                command = %command,
+                "Refused management command, system commands are disabled"
+            );
+            anyhow::bail!("disabled");
+        }
+        let Some(mgmt_command) = mgmt::lookup(name) else {
+            warn!(
+                reason_code = Rejection::UnauthorizedSystemCmd.code(),
+                command = %command,
+                "Blocked unauthorized system command"
+            );
+            anyhow::bail!("Unauthorized system command: {}", command);
+        };
+        return mgmt::run(mgmt_command, args, controller).await;
+    }
+
+    let mut response = String::new();
+    match command {
+        "get_meta" => {
+            let info = rc::parse_info(&forward_query(b"info\n", "info", controller).await?);
+            response = serde_json::to_string(&info)?;
+        }
+        "list_commands" => {
+            response = serde_json::to_string(&commands::list(&controller.mgmt_prefix))?;
+        }
+        "list_allowed" => {
+            response = serde_json::to_string(&commands::allowed(controller, client_addr))?;
+        }
+        "version" => {
+            response = serde_json::to_string(&commands::build_info())?;
+        }
+        _ if verb == "expect" => {
+            response = expect_response(args, client_addr, controller).await?;
+        }
+        "playlist_clear" => {
+            playlist_clear(controller).await?;
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        // Bare `fullscreen` stays VLC's toggle; with an argument it sets the state.
+        _ if verb == "fullscreen" && !args.is_empty() => {
+            let state = match args {
+                "on" | "off" => args,
+                other => anyhow::bail!("Invalid fullscreen argument: {} (expected on or off)", other),
+            };
+            set_fullscreen(controller, state).await?;
+            response = format!("fullscreen {} sent (unconfirmed)", state);
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        _ if verb == "mute" => {
+            let mute = match args {
+                "on" => true,
+                "off" => false,
+                other => anyhow::bail!("Invalid mute argument: '{}' (expected on or off)", other),
+            };
+            response = set_mute(controller, mute).await?.to_string();
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        _ if verb == "swap_to" => {
+            let (uri, position) = parse_swap_to(args)?;
+            response = swap_to(controller, uri, position).await?;
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        "restart_item" => {
+            response = restart_item(controller).await?;
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        // Replaces RC's own is_playing (1 or 0) with a reading of the status.
+        "is_playing" => {
+            let status = rc::parse_status(&forward_query(b"status\n", "status", controller).await?);
+            response = status.is_playing().to_string();
+        }
+        "get_rate" => {
+            response = read_rate(controller).await?.to_string();
+        }
+        // RC's get_time stays as it is; this answers a progress bar in one request.
+        "get_progress" => {
+            let time = rc::parse_seconds(&forward_query(b"get_time\n", "get_time", controller).await?);
+            let length = rc::parse_seconds(&forward_query(b"get_length\n", "get_length", controller).await?);
+            response = serde_json::json!({ "time": time, "length": length }).to_string();
+        }
+        _ if verb == "rate_set" => {
+            let rate = args
+                .parse::<f64>()
+                .ok()
+                .filter(|rate| (MIN_RATE..=MAX_RATE).contains(rate))
+                .ok_or_else(|| anyhow::anyhow!("Invalid rate: '{}' (expected {} to {})", args, MIN_RATE, MAX_RATE))?;
+            response = set_rate(controller, rate).await?.to_string();
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        _ if verb == "rate_up" || verb == "rate_down" => {
+            let step = match args {
+                "" => DEFAULT_RATE_STEP,
+                step => step
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|step| *step > 0.0 && *step <= MAX_RATE)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid rate step: '{}' (expected a positive number)", step))?,
+            };
+            let current = read_rate(controller).await?;
+            let target = if verb == "rate_up" { current + step } else { current - step };
+            response = set_rate(controller, target.clamp(MIN_RATE, MAX_RATE)).await?.to_string();
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        "get_volume" => {
+            response = volume_percent(read_volume(controller).await?).to_string();
+        }
+        _ if verb == "stop_after_current" => {
+            let action = match args {
+                "" | "stop" => EndAction::Stop,
+                "pause" => EndAction::Pause,
+                "off" => {
+                    if !controller.stop_after_current.disarm() {
+                        anyhow::bail!("No stop after current item pending");
+                    }
+                    return Ok(response);
+                }
+                other => anyhow::bail!("Invalid stop_after_current argument: '{}' (expected stop, pause or off)", other),
+            };
+            response = poller::arm(controller, action).await?;
+        }
+        _ if verb == "loop_range" => {
+            let range = args
+                .split_once(' ')
+                .and_then(|(start, end)| {
+                    Some(poller::Range {
+                        start: start.trim().parse().ok()?,
+                        end: end.trim().parse().ok()?,
+                    })
+                })
+                .filter(|range| range.start > 0 && range.start <= range.end)
+                .ok_or_else(|| anyhow::anyhow!("Invalid loop_range: '{}' (expected 'loop_range <start> <end>', from 1)", args))?;
+            poller::loop_range(controller, range).await?;
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        "loop_clear" => {
+            if !controller.loop_range.clear() {
+                anyhow::bail!("No playlist range is looping");
+            }
+            mirror_command(controller, command);
+        }
+        _ if verb == "load_playlist" => {
+            if args.is_empty() {
+                anyhow::bail!("Invalid load_playlist: expected 'load_playlist <path>'");
+            }
+            let file = playlist_file::resolve(args, controller.playlist_dir.as_deref())?;
+            response = playlist_file::load(controller, &file).await?;
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        "snapshot" => {
+            response = snapshot::take(controller, controller.snapshot_dir.as_deref()).await?;
+        }
+        "list_tracks" => {
+            let audio = rc::parse_tracks(&controller.forward(b"atrack\n").await?);
+            let subtitle = rc::parse_tracks(&controller.forward(b"strack\n").await?);
+            response = serde_json::json!({ "audio": audio, "subtitle": subtitle }).to_string();
+        }
+        _ if verb == "audio_track" || verb == "sub_track" => {
+            let rc_verb = if verb == "audio_track" { "atrack" } else { "strack" };
+            select_track(controller, rc_verb, args).await?;
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        _ if verb == "aspect" => {
+            if !is_ratio(args) {
+                anyhow::bail!("Invalid aspect ratio: '{}' (expected e.g. 16:9 or 2.35:1)", args);
+            }
+            controller.forward(format!("vratio {}\n", args).as_bytes()).await?;
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        _ if verb == "crop" => {
+            if !is_ratio(args) && !is_crop_geometry(args) {
+                anyhow::bail!("Invalid crop: '{}' (expected a ratio such as 16:10 or a geometry such as 1280x720+0+40)", args);
+            }
+            controller.forward(format!("vcrop {}\n", args).as_bytes()).await?;
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        "get_aspect" => {
+            let output = forward_query(b"vratio\n", "vratio", controller).await?;
+            response = rc::parse_video_setting(&output).ok_or_else(|| anyhow::anyhow!("No video output, no aspect ratio"))?;
+        }
+        _ if verb == "seek_rel" => {
+            let offset: i64 = args
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid seek_rel offset: '{}' (expected e.g. +30 or -10)", args))?;
+            response = seek_relative(controller, offset).await?.to_string();
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        _ if verb == "goto" => {
+            let position = args
+                .parse::<usize>()
+                .ok()
+                .filter(|&position| position > 0)
+                .ok_or_else(|| anyhow::anyhow!("Invalid goto position: '{}' (expected 1 or more)", args))?;
+            goto_position(controller, position).await?;
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        _ if verb == "goto_name" => {
+            if args.is_empty() {
+                anyhow::bail!("Invalid goto_name: expected part of an item name");
+            }
+            response = goto_name(controller, args).await?.to_string();
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        _ if verb == "seek_pct" => {
+            let percent = args
+                .parse::<f64>()
+                .ok()
+                .filter(|percent| (0.0..=100.0).contains(percent))
+                .ok_or_else(|| anyhow::anyhow!("Invalid seek_pct percentage: '{}' (expected 0 to 100)", args))?;
+            response = seek_percent(controller, percent).await?.to_string();
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+        }
+        _ if commands::is_vlc_query(command) => {
+            response = forward_query(data, command, controller).await?;
+            response = controller.transforms.apply(verb, response)?;
+        }
+        _ => {
+            if !commands::is_known(verb) {
+                match controller.unknown_command {
+                    UnknownCommand::Forward => {}
+                    UnknownCommand::WarnAndForward => warn!(command = %command, "Forwarding unknown command to VLC"),
+                    UnknownCommand::Reject => {
+                        warn!(
+                            reason_code = Rejection::UnknownCommand.code(),
+                            command = %command,
+                            "Rejected unknown command"
+                        );
+                        anyhow::bail!("Unknown command: {}", verb);
+                    }
+                }
+            }
+            // Assume it's a command for VLC.
+            debug!(command = %command, "Forwarding command to VLC");
+            response = controller.forward(data).await?;
+            controller.query_cache.clear();
+            mirror_command(controller, command);
+            response = controller.transforms.apply(verb, response)?;
+        }
+    }
+    Ok(response)
+}
+
+/// Runs `<regex> :: <command>` and fails unless the response matches the regex.
+///
+/// The inner command goes through the normal dispatcher, so queries may be
+/// served from the cache and commands are mirrored as usual.
+async fn expect_response(args: &str, client_addr: SocketAddr, controller: &Controller) -> Result<String> {
+    let Some((pattern, command)) = args.split_once("::") else {
+        anyhow::bail!("Invalid expect: '{}' (expected 'expect <regex> :: <command>')", args);
+    };
+    let (pattern, command) = (pattern.trim(), command.trim());
+    if pattern.is_empty() || command.is_empty() {
+        anyhow::bail!("Invalid expect: '{}' (expected 'expect <regex> :: <command>')", args);
+    }
+    // Regex errors span several lines; keep the reply on one.
+    let regex = Regex::new(pattern).map_err(|e| {
+        let reason = e.to_string().lines().last().unwrap_or_default().to_string();
+        anyhow::anyhow!("Invalid expect pattern '{}': {}", pattern, reason)
+    })?;
+
+    let response = Box::pin(dispatch_command(format!("{}\n", command).as_bytes(), client_addr, controller)).await?;
+    if !regex.is_match(&response) {
+        debug!(pattern = pattern, command = command, response = %response, "Expectation failed");
+        anyhow::bail!("FAIL /{}/ did not match: {}", pattern, response);
+    }
+    Ok(response)
+}
+
+/// Forwards a read-only query, answering from the query cache when possible.
+async fn forward_query(data: &[u8], query: &str, controller: &Controller) -> Result<String> {
+    if let Some(cached) = controller.query_cache.get(query) {
+        debug!(command = %query, "Serving query from cache");
+        return Ok(cached);
+    }
+    let response = controller
+        .coalescer
+        .run(query, async {
+            debug!(command = %query, "Forwarding query to VLC");
+            controller.forward(data).await
+        })
+        .await?;
+    if controller.query_cache.is_enabled() {
+        controller.query_cache.insert(query.to_string(), response.clone());
+    }
+    Ok(response)
+}
+
+/// Best-effort copy of a successful VLC command to the mirror controller.
+///
+/// Runs in the background so the client reply never waits on the mirror;
+/// failures are only logged.
+fn mirror_command(controller: &Controller, command: &str) {
+    let Some(mirror_addr) = controller.mirror_addr.clone() else {
+        return;
+    };
+    let command = command.to_string();
+    tokio::spawn(async move {
+        match send_to_mirror(&mirror_addr, &command).await {
+            Ok(reply) if reply.starts_with("OK") => {
+                debug!(mirror_addr = %mirror_addr, command = %command, "Mirrored command");
+            }
+            Ok(reply) => {
+                warn!(mirror_addr = %mirror_addr, command = %command, reply = %reply, "Mirror rejected command");
+            }
+            Err(e) => {
+                warn!(mirror_addr = %mirror_addr, command = %command, error = %e, "Failed to mirror command");
+            }
+        }
+    });
+}
+
+/// Sends one command line to another vlc-control instance and returns its reply line.
+async fn send_to_mirror(mirror_addr: &str, command: &str) -> Result<String> {
+    let mut stream = tokio::time::timeout(MIRROR_TIMEOUT, TcpStream::connect(mirror_addr)).await??;
+    stream.write_all(format!("{}\n", command).as_bytes()).await?;
+
+    let mut reply = String::new();
+    tokio::time::timeout(MIRROR_TIMEOUT, BufReader::new(&mut stream).read_line(&mut reply)).await??;
+    Ok(reply.trim().to_string())
+}
+
+/// Explicitly switches fullscreen `on` or `off` instead of toggling.
+///
+/// RC `status` cannot report the fullscreen state, so this checks that media
+/// is loaded (fullscreen needs a video output) and then sends VLC's explicit
+/// `fullscreen on|off`, which is idempotent. VLC does not acknowledge it
+/// either, so the reply only says the command was sent, not that the video
+/// output actually changed.
+async fn set_fullscreen(controller: &Controller, state: &str) -> Result<()> {
+    let status = rc::parse_status(&controller.forward(b"status\n").await?);
+    if !status.has_media() {
+        anyhow::bail!("No media playing, cannot set fullscreen {}", state);
+    }
+    controller.forward(format!("fullscreen {}\n", state).as_bytes()).await?;
+    info!(state = state, input = status.input.as_deref(), "Set fullscreen");
+    Ok(())
+}
+
+/// Selects track `arg` with `atrack`/`strack` after checking that VLC lists it.
+async fn select_track(controller: &Controller, rc_verb: &str, arg: &str) -> Result<()> {
+    let id: i64 = arg
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid track index: '{}' (expected a number, -1 disables)", arg))?;
+    let tracks = rc::parse_tracks(&controller.forward(format!("{}\n", rc_verb).as_bytes()).await?);
+    if tracks.is_empty() {
+        anyhow::bail!("No media playing, no tracks to select");
+    }
+    if !tracks.iter().any(|track| track.id == id) {
+        let ids: Vec<String> = tracks.iter().map(|track| track.id.to_string()).collect();
+        anyhow::bail!("No track {} (available: {})", id, ids.join(", "));
+    }
+    controller.forward(format!("{} {}\n", rc_verb, id).as_bytes()).await?;
+    info!(command = rc_verb, track = id, "Selected track");
+    Ok(())
+}
+
+/// Seeks `offset` seconds from the current position, clamped to the media
+/// length, and returns the absolute target.
+async fn seek_relative(controller: &Controller, offset: i64) -> Result<u64> {
+    let time = rc::parse_seconds(&controller.forward(b"get_time\n").await?);
+    let length = rc::parse_seconds(&controller.forward(b"get_length\n").await?);
+    let (Some(time), Some(length)) = (time, length) else {
+        anyhow::bail!("No media playing, cannot seek");
+    };
+
+    let target = time.saturating_add_signed(offset).min(length);
+    controller.forward(format!("seek {}\n", target).as_bytes()).await?;
+    debug!(time = time, offset = offset, target = target, "Relative seek");
+    Ok(target)
+}
+
+/// Resumes the current item if it is paused or stopped and, once status
+/// confirms it plays, seeks it back to the start. Returns the item's input.
+async fn restart_item(controller: &Controller) -> Result<String> {
+    let status = rc::parse_status(&controller.forward(b"status\n").await?);
+    let Some(input) = status.input.clone() else {
+        anyhow::bail!("No media playing, nothing to restart");
+    };
+    if !status.is_playing() {
+        controller.forward(b"play\n").await?;
+        wait_for_playback(controller, &input, rc::Status::is_playing).await?;
+    }
+    controller.forward(b"seek 0\n").await?;
+    info!(input = %input, "Restarted current item");
+    Ok(input)
+}
+
+/// Polls status until `started` accepts it, for up to `PLAYBACK_START_TIMEOUT`,
+/// and returns that status. `what` names the media in the error.
+async fn wait_for_playback(controller: &Controller, what: &str, started: impl Fn(&rc::Status) -> bool) -> Result<rc::Status> {
+    let polling_since = Instant::now();
+    loop {
+        let status = rc::parse_status(&controller.forward(b"status\n").await?);
+        if started(&status) {
+            return Ok(status);
+        }
+        if polling_since.elapsed() >= PLAYBACK_START_TIMEOUT {
+            anyhow::bail!(
+                "{} did not start within {}s, VLC state is {}",
+                what,
+                PLAYBACK_START_TIMEOUT.as_secs(),
+                status.state.as_deref().unwrap_or("unknown")
+            );
+        }
+        tokio::time::sleep(PLAYBACK_POLL_INTERVAL).await;
+    }
+}
+
+/// Splits `swap_to` arguments into the URI (with a scheme, or an absolute
+/// path) and the optional start position in seconds.
+fn parse_swap_to(args: &str) -> Result<(&str, Option<u64>)> {
+    let usage = || anyhow::anyhow!("Invalid swap_to: '{}' (expected 'swap_to <uri> [position_secs]')", args);
+    let mut parts = args.split_whitespace();
+    let (Some(uri), position, None) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(usage());
+    };
+    let scheme = uri.split_once("://").map(|(scheme, _)| scheme);
+    let valid_scheme = scheme.is_some_and(|scheme| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+    if !valid_scheme && !uri.starts_with('/') {
+        anyhow::bail!("Invalid swap_to URI: '{}' (expected e.g. file:///media/intro.mp4)", uri);
+    }
+    let position = position
+        .map(|position| position.parse::<u64>().map_err(|_| anyhow::anyhow!("Invalid swap_to position: '{}' (expected seconds)", position)))
+        .transpose()?;
+    Ok((uri, position))
+}
+
+/// Replaces the current item with `uri` and, once status reports it
+/// playing, seeks to `position`. Returns the new input.
+///
+/// Until VLC has opened the new item, status may still report the old one
+/// as playing, so only a status naming `uri` counts.
+async fn swap_to(controller: &Controller, uri: &str, position: Option<u64>) -> Result<String> {
+    controller.forward(b"stop\n").await?;
+    controller.forward(format!("add {}\n", uri).as_bytes()).await?;
+
+    let started = |status: &rc::Status| {
+        status.is_playing() && status.input.as_deref().is_some_and(|input| same_media(input, uri))
+    };
+    let status = wait_for_playback(controller, uri, started).await?;
+    let Some(input) = status.input else {
+        unreachable!("accepted only with an input");
+    };
+    if let Some(position) = position.filter(|&position| position > 0) {
+        controller.forward(format!("seek {}\n", position).as_bytes()).await?;
+    }
+    info!(input = %input, position = position, "Swapped to new media");
+    Ok(input)
+}
+
+/// True if VLC's `input` is `uri`. VLC reports a file path as a `file://`
+/// URI and percent-encodes it, so both sides are compared decoded.
+fn same_media(input: &str, uri: &str) -> bool {
+    let uri = if uri.starts_with('/') { format!("file://{}", uri) } else { uri.to_string() };
+    percent_decode(input) == percent_decode(&uri)
+}
+
+/// Decodes `%XX` escapes, leaving malformed ones as they are.
+fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Seeks to `percent` of the media length, rounded to the nearest second,
+/// and returns the absolute target.
+async fn seek_percent(controller: &Controller, percent: f64) -> Result<u64> {
+    let Some(length) = rc::parse_seconds(&controller.forward(b"get_length\n").await?) else {
+        anyhow::bail!("No media playing, cannot seek");
+    };
+    if length == 0 {
+        anyhow::bail!("Input has no length, cannot seek by percentage");
+    }
+    let target = ((length as f64 * percent / 100.0).round() as u64).min(length);
+    controller.forward(format!("seek {}\n", target).as_bytes()).await?;
+    debug!(length = length, percent = percent, target = target, "Percentage seek");
+    Ok(target)
+}
+
+/// Mutes by setting the volume to 0, or restores the volume saved by the
+/// last mute, and returns the volume VLC reports afterwards.
+///
+/// RC's own `mute` only toggles and cannot be confirmed, so muting is done
+/// through `volume`, which can be read back.
+async fn set_mute(controller: &Controller, mute: bool) -> Result<u32> {
+    let current = read_volume(controller).await?;
+    let target = if mute {
+        if current == 0 {
+            return Ok(0);
+        }
+        *controller.saved_volume.lock().unwrap() = Some(current);
+        0
+    } else {
+        let saved = controller.saved_volume.lock().unwrap().take();
+        // A non-zero volume means it was already unmuted, e.g. by a `volume` command.
+        if current > 0 {
+            return Ok(current);
+        }
+        saved.ok_or_else(|| anyhow::anyhow!("Volume is 0 and there is no saved volume to restore"))?
+    };
+
+    controller.forward(format!("volume {}\n", target).as_bytes()).await?;
+    let confirmed = read_volume(controller).await?;
+    if confirmed != target {
+        anyhow::bail!("Volume is {} after mute {}, expected {}", confirmed, if mute { "on" } else { "off" }, target);
+    }
+    info!(muted = mute, volume = confirmed, "Set mute");
+    Ok(confirmed)
+}
+
+async fn read_rate(controller: &Controller) -> Result<f64> {
+    let output = controller.forward(b"rate\n").await?;
+    rc::parse_rate(&output).ok_or_else(|| anyhow::anyhow!("Unexpected rate reply from VLC: '{}'", output))
+}
+
+/// Sets the playback rate, rounded to hundredths, and returns it.
+async fn set_rate(controller: &Controller, rate: f64) -> Result<f64> {
+    let rate = (rate * 100.0).round() / 100.0;
+    controller.forward(format!("rate {}\n", rate).as_bytes()).await?;
+    info!(rate = rate, "Set playback rate");
+    Ok(rate)
+}
+
+/// True for a ratio of two positive numbers, such as `16:9` or `2.35:1`.
+fn is_ratio(ratio: &str) -> bool {
+    let positive = |n: &str| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit() || b == b'.') && n.parse::<f64>().is_ok_and(|n| n > 0.0);
+    ratio.split_once(':').is_some_and(|(w, h)| positive(w) && positive(h))
+}
+
+/// True for a `<width>x<height>+<left>+<top>` crop geometry in pixels.
+fn is_crop_geometry(geometry: &str) -> bool {
+    let number = |n: &str| n.bytes().all(|b| b.is_ascii_digit()).then(|| n.parse::<u32>().ok()).flatten();
+    let Some((size, offsets)) = geometry.split_once('+') else {
+        return false;
+    };
+    let (Some((width, height)), Some((left, top))) = (size.split_once('x'), offsets.split_once('+')) else {
+        return false;
+    };
+    matches!([width, height, left, top].map(number), [Some(width), Some(height), Some(_), Some(_)] if width > 0 && height > 0)
+}
+
+/// Converts VLC's volume (256 is 100%, at most 320) to a rounded percentage.
+fn volume_percent(volume: u32) -> u32 {
+    (volume.saturating_mul(100) + VLC_FULL_VOLUME / 2) / VLC_FULL_VOLUME
+}
+
+async fn read_volume(controller: &Controller) -> Result<u32> {
+    let output = controller.forward(b"volume\n").await?;
+    rc::parse_volume(&output).ok_or_else(|| anyhow::anyhow!("Unexpected volume reply from VLC: '{}'", output))
+}
+
+/// Plays the item at 1-based `position` of the playlist, failing if there is none.
+async fn goto_position(controller: &Controller, position: usize) -> Result<()> {
+    let items = rc::parse_playlist(&controller.forward(b"playlist\n").await?);
+    if position > items.len() {
+        anyhow::bail!("No playlist item at position {}, the playlist has {} item(s)", position, items.len());
+    }
+    controller.forward(format!("goto {}\n", position).as_bytes()).await?;
+    info!(position = position, name = %items[position - 1].name, "Jumped to playlist item");
+    Ok(())
+}
+
+/// Plays the one playlist item whose name contains `text` (ignoring case)
+/// and returns its position.
+async fn goto_name(controller: &Controller, text: &str) -> Result<usize> {
+    let items = rc::parse_playlist(&controller.forward(b"playlist\n").await?);
+    let needle = text.to_lowercase();
+    let matches: Vec<(usize, &str)> = items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| item.name.to_lowercase().contains(&needle))
+        .map(|(index, item)| (index + 1, item.name.as_str()))
+        .collect();
+    let position = match matches.as_slice() {
+        [] => anyhow::bail!("No playlist item matches '{}'", text),
+        [(position, _)] => *position,
+        _ => {
+            let names: Vec<&str> = matches.iter().map(|(_, name)| *name).collect();
+            anyhow::bail!("{} playlist items match '{}': {}", matches.len(), text, names.join(", "));
+        }
+    };
+    controller.forward(format!("goto {}\n", position).as_bytes()).await?;
+    info!(position = position, name = %items[position - 1].name, "Jumped to playlist item");
+    Ok(position)
+}
+
+/// Clears the VLC playlist and confirms it is empty by listing it again.
+async fn playlist_clear(controller: &Controller) -> Result<()> {
+    controller.forward(b"clear\n").await?;
+    let listing = controller.forward(b"playlist\n").await?;
+    let remaining = rc::parse_playlist(&listing);
+    if !remaining.is_empty() {
+        let names: Vec<&str> = remaining.iter().map(|item| item.name.as_str()).collect();
+        warn!(remaining = remaining.len(), "Playlist not empty after clear");
+        anyhow::bail!("playlist not empty after clear, {} item(s) remain: {}", names.len(), names.join(", "));
+    }
+    info!("Playlist cleared");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::MockVlc;
+
+    fn silent_vlc() -> Arc<MockVlc> {
+        MockVlc::new(|_| Ok(String::new()))
+    }
+
+    #[tokio::test]
+    async fn forwards_plain_commands() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&[], vlc.clone());
+        assert_eq!(testing::run(&controller, "play").await.unwrap(), "");
+        assert_eq!(vlc.sent(), ["play"]);
+    }
+
+    #[tokio::test]
+    async fn counts_commands_by_transport() {
+        let controller = testing::controller(&[], silent_vlc());
+        testing::run(&controller, "play").await.unwrap();
+        let metrics = controller.metrics.render(&controller.breaker.status(), &controller.vlc.connection_stats());
+        assert!(metrics.contains("vlc_control_commands_total{transport=\"tcp\",outcome=\"ok\"} 1"));
+        assert!(metrics.contains("vlc_control_commands_total{transport=\"udp\",outcome=\"ok\"} 0"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn counts_system_commands_by_exit_code() {
+        use std::os::unix::process::ExitStatusExt;
+        let metrics = metrics::Metrics::default();
+        metrics.system_command("reboot", &Ok(std::process::ExitStatus::from_raw(0)));
+        metrics.system_command("reboot", &Ok(std::process::ExitStatus::from_raw(0)));
+        metrics.system_command("restart_vlc", &Ok(std::process::ExitStatus::from_raw(5 << 8)));
+        metrics.system_command("shutdown", &Err(std::io::ErrorKind::NotFound.into()));
+        let rendered = metrics.render(&breaker::CircuitBreaker::new(0, Duration::ZERO).status(), &Default::default());
+        assert!(rendered.contains("vlc_control_system_commands_total{command=\"reboot\",outcome=\"success\",exit_code=\"0\"} 2"));
+        assert!(rendered.contains("vlc_control_system_commands_total{command=\"restart_vlc\",outcome=\"failure\",exit_code=\"5\"} 1"));
+        assert!(rendered.contains("vlc_control_system_commands_total{command=\"shutdown\",outcome=\"failure\",exit_code=\"none\"} 1"));
+        assert!(!rendered.contains("vlc_control_vlc_reconnects_total"));
+
+        let vlc = vlc::ConnectionStats {
+            persistent: true,
+            reconnects: 3,
+            connection_age_secs: Some(42),
+            ..Default::default()
+        };
+        let rendered = metrics.render(&breaker::CircuitBreaker::new(0, Duration::ZERO).status(), &vlc);
+        assert!(rendered.contains("vlc_control_vlc_reconnects_total 3\n"));
+        assert!(rendered.contains("vlc_control_vlc_connection_age_seconds 42\n"));
+    }
+
+    #[test]
+    fn echo_shows_the_parsed_command() {
+        assert_eq!(echo_line(b"  play\r\n"), "ECHO play\n");
+        assert_eq!(echo_line(b"seek\t30 \xff\n"), "ECHO seek\\t30 \u{fffd}\n");
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_commands_before_vlc() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&["--max-command-size", "8"], vlc.clone());
+        let err = testing::run(&controller, "add file:///long.mp4").await.unwrap_err();
+        assert!(err.to_string().starts_with("Command too large"));
+        assert!(vlc.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_utf8() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&[], vlc.clone());
+        let client_addr = testing::CLIENT_ADDR.parse().unwrap();
+        assert!(process_command(b"play \xff\n", client_addr, Transport::Tcp, &controller).await.is_err());
+        assert!(vlc.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_unregistered_management_commands() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&["--mgmt-prefix", "adm_"], vlc.clone());
+        let err = testing::run(&controller, "adm_format_disk").await.unwrap_err();
+        assert!(err.to_string().starts_with("Unauthorized system command"));
+        assert!(vlc.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn disabled_system_commands_are_refused() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&["--disable-system-commands"], vlc.clone());
+        let result = testing::run(&controller, "pi_reboot").await;
+        assert_eq!(format_reply(&result), "ERR disabled\n");
+        assert!(testing::run(&controller, "play").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn read_only_refuses_mutating_commands() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&["--read-only"], vlc.clone());
+        for command in ["play", "volume 200", "seek_rel 10", "pi_reboot", "detach stop", "expect . :: next", "unknown_verb"] {
+            assert_eq!(format_reply(&testing::run(&controller, command).await), "ERR read_only\n", "{}", command);
+        }
+        for command in ["status", "volume", "list_commands", "pi_status", "expect .* :: playlist"] {
+            assert!(testing::run(&controller, command).await.is_ok(), "{}", command);
+        }
+        for command in ["status\rquit", "status\nshutdown", "detach status\rquit"] {
+            let err = testing::run(&controller, command).await.unwrap_err();
+            assert!(err.to_string().contains("line break"), "{:?}: {}", command, err);
+        }
+        assert_eq!(vlc.sent(), ["status", "volume", "playlist"]);
+    }
+
+    #[tokio::test]
+    async fn list_allowed_reflects_the_settings() {
+        let controller = testing::controller(&["--disable-system-commands", "--unknown-command", "reject"], silent_vlc());
+        let allowed: serde_json::Value = serde_json::from_str(&testing::run(&controller, "list_allowed").await.unwrap()).unwrap();
+        assert_eq!(allowed["unknown_commands"], "reject");
+        assert_eq!(allowed["system_commands_enabled"], false);
+        let commands = allowed["commands"].as_array().unwrap();
+        assert!(commands.contains(&"play".into()) && !commands.contains(&"pi_reboot".into()));
+    }
+
+    #[tokio::test]
+    async fn joins_continued_tcp_lines() {
+        let controller = testing::controller(&["--max-command-size", "24"], silent_vlc());
+        let input: &[u8] = b"add file:///a\\\r\n.mp4\nstatus\nenqueue \\\n 0123456789 \\\n 0123456789\nplay\n";
+        let mut reader = BufReader::new(input);
+        let mut commands = Vec::new();
+        loop {
+            let mut buf = Vec::new();
+            match read_command(&mut reader, &mut buf, &controller).await.unwrap() {
+                Frame::Eof => break,
+                Frame::Line => commands.push(String::from_utf8(buf).unwrap()),
+                Frame::CommandTooLarge => commands.push("too large".to_string()),
+                Frame::Oversized => commands.push("oversized".to_string()),
+            }
+        }
+        assert_eq!(commands, ["add file:///a.mp4\n", "status\n", "too large", "play\n"]);
+    }
+
+    #[tokio::test]
+    async fn reports_completions_to_the_sink() {
+        let path = std::env::temp_dir().join(format!("vlc-control-completions-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let controller = testing::controller(&["--completion-sink", path.to_str().unwrap()], silent_vlc());
+        testing::run(&controller, "play").await.unwrap();
+        testing::run(&controller, "pi_nope").await.unwrap_err();
+
+        let mut lines = Vec::new();
+        for _ in 0..100 {
+            lines = std::fs::read_to_string(&path).unwrap_or_default().lines().map(str::to_string).collect();
+            if lines.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        std::fs::remove_file(&path).unwrap();
+        let entries: Vec<serde_json::Value> = lines.iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!((&entries[0]["verb"], &entries[0]["outcome"]), (&"play".into(), &"ok".into()));
+        assert_eq!((&entries[1]["verb"], &entries[1]["outcome"]), (&"pi_nope".into(), &"error".into()));
+    }
+
+    #[tokio::test]
+    async fn reports_connection_events() {
+        let path = std::env::temp_dir().join(format!("vlc-control-connection-events-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let args = Args::parse_from(["vlc-control", "--connection-events", path.to_str().unwrap()]);
+        let config = "[profiles.kiosk]\ncommands = [\"play\"]\n[[clients]]\ncidr = \"127.0.0.0/8\"\nprofile = \"kiosk\"\n";
+        let access = AccessPolicy::from_config(toml::from_str(config).unwrap()).unwrap();
+        let controller = Arc::new(Controller::new(&args, silent_vlc(), access, Default::default(), Default::default(), None));
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        let addr = testing::CLIENT_ADDR.parse().unwrap();
+        let served = tokio::spawn({
+            let controller = controller.clone();
+            async move { serve_and_report(server, addr, &controller).await }
+        });
+        client.write_all(b"play\npause\n").await.unwrap();
+        client.shutdown().await.unwrap();
+        client.read_to_end(&mut Vec::new()).await.unwrap();
+        served.await.unwrap().unwrap();
+
+        let mut lines = Vec::new();
+        for _ in 0..100 {
+            lines = std::fs::read_to_string(&path).unwrap_or_default().lines().map(str::to_string).collect();
+            if lines.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        std::fs::remove_file(&path).unwrap();
+        let events: Vec<serde_json::Value> = lines.iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let kinds: Vec<(&str, &str)> = events.iter().map(|event| (event["event"].as_str().unwrap(), event["reason"].as_str().unwrap_or(""))).collect();
+        assert_eq!(kinds, [("opened", ""), ("rejected", "not_permitted"), ("closed", "disconnected")]);
+        assert_eq!(events[0]["client_addr"], testing::CLIENT_ADDR);
+        assert!(controller.metrics.render(&controller.breaker.status(), &controller.vlc.connection_stats()).contains("vlc_control_rejections_total{reason=\"not_permitted\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn aliases_expand_before_validation() {
+        let args = Args::parse_from(["vlc-control"]);
+        let aliases = [("play_at", "goto {0}"), ("louder", "volume {0}")].map(|(name, template)| (name.to_string(), template.to_string()));
+        let aliases = Aliases::from_config(aliases.into()).unwrap();
+        let vlc = MockVlc::new(|command| match command {
+            "playlist" => Ok(testing::Transcript::parse(testing::FIXTURES[1].1).reply("playlist").unwrap().to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = Arc::new(Controller::new(&args, vlc.clone(), None, Default::default(), aliases, None));
+
+        testing::run(&controller, "play_at 2").await.unwrap();
+        assert!(testing::run(&controller, "play_at 3").await.unwrap_err().to_string().contains("has 2 item(s)"));
+        assert!(testing::run(&controller, "play_at").await.unwrap_err().to_string().contains("takes 1 argument(s)"));
+        assert!(testing::run(&controller, "detach louder").await.is_err());
+        assert_eq!(testing::run(&controller, "detach louder 200").await.unwrap(), "detached");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(vlc.sent().last().unwrap(), "volume 200");
+        assert!(vlc.sent().contains(&"goto 2".to_string()));
+    }
+
+    #[tokio::test]
+    async fn version_is_open_to_every_client() {
+        let args = Args::parse_from(["vlc-control"]);
+        let config = "[profiles.kiosk]\ncommands = [\"play\"]\n[[clients]]\ncidr = \"127.0.0.0/8\"\nprofile = \"kiosk\"\n";
+        let access = AccessPolicy::from_config(toml::from_str(config).unwrap()).unwrap();
+        let controller = Arc::new(Controller::new(&args, silent_vlc(), access, Default::default(), Default::default(), None));
+        assert!(testing::run(&controller, "pause").await.is_err());
+        assert!(testing::run(&controller, "version --verbose").await.is_ok());
+
+        let version: serde_json::Value = serde_json::from_str(&testing::run(&controller, "version").await.unwrap()).unwrap();
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert!(version["build_timestamp"].as_str().unwrap().ends_with('Z'));
+        let allowed = commands::allowed(&controller, testing::CLIENT_ADDR.parse().unwrap());
+        assert_eq!(allowed.commands, ["version", "play"]);
+    }
+
+    #[tokio::test]
+    async fn set_log_level_validates_the_level() {
+        let controller = testing::controller(&[], silent_vlc());
+        let err = testing::run(&controller, "pi_set_log_level loud").await.unwrap_err();
+        assert!(err.to_string().contains("Invalid log level"));
+        let err = testing::run(&controller, "pi_set_log_level debug").await.unwrap_err();
+        assert!(err.to_string().contains("cannot be changed"));
+        assert!(testing::run(&controller, "pi_reset_log_level now").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn goto_checks_the_playlist() {
+        let vlc = MockVlc::new(|command| match command {
+            "playlist" => Ok(testing::Transcript::parse(testing::FIXTURES[1].1).reply("playlist").unwrap().to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = testing::controller(&[], vlc.clone());
+        testing::run(&controller, "goto 2").await.unwrap();
+        assert!(testing::run(&controller, "goto 3").await.unwrap_err().to_string().contains("has 2 item(s)"));
+        assert!(testing::run(&controller, "goto 0").await.is_err());
+
+        assert_eq!(testing::run(&controller, "goto_name bunny").await.unwrap(), "2");
+        assert!(testing::run(&controller, "goto_name outro").await.unwrap_err().to_string().contains("No playlist item"));
+        let ambiguous = testing::run(&controller, "goto_name i").await.unwrap_err().to_string();
+        assert!(ambiguous.starts_with("2 playlist items match"), "{}", ambiguous);
+        let gotos: Vec<String> = vlc.sent().into_iter().filter(|sent| sent.starts_with("goto")).collect();
+        assert_eq!(gotos, ["goto 2", "goto 2"]);
+    }
+
+    #[tokio::test]
+    async fn loop_range_returns_to_its_start() {
+        let vlc = MockVlc::new(|command| match command {
+            "playlist" => Ok(testing::Transcript::parse(testing::FIXTURES[1].1).reply("playlist").unwrap().to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = testing::controller(&[], vlc.clone());
+        assert!(testing::run(&controller, "loop_range 2 1").await.is_err());
+        assert!(testing::run(&controller, "loop_range 1 3").await.unwrap_err().to_string().contains("has 2 item(s)"));
+        testing::run(&controller, "loop_range 1 1").await.unwrap();
+
+        // The fixture's current item is the second, outside the range.
+        let poller = tokio::spawn(poller::run(controller.clone()));
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        poller.abort();
+        let gotos: Vec<String> = vlc.sent().into_iter().filter(|sent| sent.starts_with("goto")).collect();
+        assert_eq!(gotos, ["goto 1", "goto 1"]);
+
+        testing::run(&controller, "loop_clear").await.unwrap();
+        assert!(testing::run(&controller, "loop_clear").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn auto_detect_answers_json_clients_in_json() {
+        let controller = testing::controller(&["--tcp-auto-detect"], silent_vlc());
+        let (client, server) = tokio::io::duplex(4096);
+        let addr = testing::CLIENT_ADDR.parse().unwrap();
+        tokio::spawn(async move { serve_commands(server, addr, &controller).await });
+
+        let (reader, mut writer) = tokio::io::split(client);
+        writer.write_all(b"{\"id\": 7, \"command\": \"play\"}\nplay\n{\"command\": \"pi_nope\"}\n").await.unwrap();
+        let mut lines = BufReader::new(reader).lines();
+        let mut replies = Vec::new();
+        for _ in 0..3 {
+            replies.push(serde_json::from_str::<serde_json::Value>(&lines.next_line().await.unwrap().unwrap()).unwrap());
+        }
+        assert_eq!(replies[0], serde_json::json!({ "id": 7, "ok": true, "response": "" }));
+        assert!(replies[1]["error"].as_str().unwrap().starts_with("Invalid JSON request"));
+        assert_eq!(replies[2]["ok"], false);
+    }
+
+    #[tokio::test]
+    async fn json_commands_with_control_characters_are_refused() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&["--tcp-auto-detect", "--read-only"], vlc.clone());
+        let (client, server) = tokio::io::duplex(4096);
+        let addr = testing::CLIENT_ADDR.parse().unwrap();
+        tokio::spawn(async move { serve_commands(server, addr, &controller).await });
+
+        let (reader, mut writer) = tokio::io::split(client);
+        writer.write_all(b"{\"command\": \"status\\nquit\"}\n{\"command\": \"status\\u0000\"}\n").await.unwrap();
+        let mut lines = BufReader::new(reader).lines();
+        for _ in 0..2 {
+            let reply: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+            assert!(reply["error"].as_str().unwrap().contains("control character"), "{}", reply);
+        }
+        assert!(vlc.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn auto_detect_reads_proxy_headers_only_from_trusted_proxies() {
+        let cases = [
+            ("192.0.2.0/24", "127.0.0.1"),
+            ("127.0.0.1", "203.0.113.9"),
+            // IPv4-mapped ranges must not widen to every IPv4 peer.
+            ("::ffff:192.0.2.0/120", "127.0.0.1"),
+            ("::ffff:127.0.0.0/104", "203.0.113.9"),
+        ];
+        for (trusted, client_ip) in cases {
+            let controller = testing::controller(&["--tcp-auto-detect", "--trusted-proxies", trusted], silent_vlc());
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listen_addr = listener.local_addr().unwrap();
+            let server = controller.clone();
+            tokio::spawn(async move {
+                let (socket, addr) = listener.accept().await.unwrap();
+                handle_tcp_connection(socket, addr, None, &server).await
+            });
+
+            let mut client = TcpStream::connect(listen_addr).await.unwrap();
+            client.write_all(b"PROXY TCP4 203.0.113.9 192.0.2.1 40000 4212\r\nplay\n").await.unwrap();
+            client.shutdown().await.unwrap();
+            client.read_to_end(&mut Vec::new()).await.unwrap();
+            let history = controller.history.recent(10);
+            let play = history.iter().find(|entry| entry.verb == "play").unwrap();
+            assert_eq!(play.client_addr.ip().to_string(), client_ip, "--trusted-proxies {}", trusted);
+        }
+        let args = ["vlc-control", "--tcp-auto-detect", "--trusted-proxies", "::ffff:0.0.0.0/64"];
+        assert!(Args::try_parse_from(args).is_err());
+    }
+
+    #[tokio::test]
+    async fn udp_commands_without_a_newline_reach_vlc_terminated() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&[], vlc.clone());
+        let addr = testing::CLIENT_ADDR.parse().unwrap();
+        assert_eq!(udp_replies(b"play", addr, &controller).await, "OK\n");
+        assert_eq!(udp_replies(b"volume 200\r\nnext", addr, &controller).await, "OK\nOK\n");
+        assert_eq!(udp_replies(b"\nstop\n\r\n \n", addr, &controller).await, "OK\n");
+        assert_eq!(vlc.received(), [&b"play\n"[..], b"volume 200\n", b"next\n", b"stop\n"]);
+    }
+
+    #[tokio::test]
+    async fn udp_datagrams_from_other_source_ports_are_dropped() {
+        let allowed = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = allowed.local_addr().unwrap().port();
+        let vlc = silent_vlc();
+        let controller = testing::controller(&["--udp-source-ports", &port.to_string()], vlc.clone());
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        tokio::spawn(run_udp_server(server, controller));
+
+        let mut buf = [0; 64];
+        other.send_to(b"stop\n", server_addr).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(200), other.recv(&mut buf)).await.is_err());
+        allowed.send_to(b"play\n", server_addr).await.unwrap();
+        let len = tokio::time::timeout(Duration::from_secs(2), allowed.recv(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"OK\n");
+        assert_eq!(vlc.sent(), ["play"]);
+    }
+
+    #[tokio::test]
+    async fn verbose_is_only_a_directive_on_the_first_line() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&[], vlc.clone());
+        let (client, server) = tokio::io::duplex(4096);
+        let addr = testing::CLIENT_ADDR.parse().unwrap();
+        tokio::spawn(async move { serve_commands(server, addr, &controller).await });
+
+        let (reader, mut writer) = tokio::io::split(client);
+        writer.write_all(b"#verbose\nplay\n#verbose\n").await.unwrap();
+        let mut lines = BufReader::new(reader).lines();
+        for _ in 0..3 {
+            assert_eq!(lines.next_line().await.unwrap().unwrap(), "OK");
+        }
+        assert_eq!(vlc.sent(), ["play", "#verbose"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_window_restarts_with_each_command() {
+        let controller = testing::controller(&[], silent_vlc());
+        let window = Duration::from_secs(60);
+        tokio::time::advance(Duration::from_secs(40)).await;
+        testing::run(&controller, "list_commands").await.unwrap();
+
+        let started = tokio::time::Instant::now();
+        controller.shutdown.idle_for(window).await;
+        assert!(started.elapsed() >= window, "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_commands_under_reject_policy() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&["--unknown-command", "reject"], vlc.clone());
+        assert!(testing::run(&controller, "plya").await.is_err());
+        assert!(testing::run(&controller, "play").await.is_ok());
+        assert_eq!(vlc.sent(), ["play"]);
+    }
+
+    #[tokio::test]
+    async fn command_prefix_filter_strips_or_rejects() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&["--command-prefix-filter", "vlc:"], vlc.clone());
+        assert!(testing::run(&controller, "vlc:play").await.is_ok());
+        let err = testing::run(&controller, "pause").await.unwrap_err();
+        assert!(err.to_string().starts_with("Missing command prefix"));
+        assert_eq!(vlc.sent(), ["play"]);
+    }
+
+    #[tokio::test]
+    async fn seek_rel_is_clamped_to_the_length() {
+        let vlc = MockVlc::new(|command| match command {
+            "get_time" => Ok("100".to_string()),
+            "get_length" => Ok("120".to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = testing::controller(&[], vlc.clone());
+        assert_eq!(testing::run(&controller, "seek_rel +30").await.unwrap(), "120");
+        assert_eq!(vlc.sent().last().unwrap(), "seek 120");
+    }
+
+    #[tokio::test]
+    async fn get_progress_is_null_without_media() {
+        let vlc = MockVlc::new(|command| match command {
+            "get_time" => Ok("100".to_string()),
+            _ => Ok("120".to_string()),
+        });
+        let controller = testing::controller(&[], vlc);
+        assert_eq!(testing::run(&controller, "get_progress").await.unwrap(), r#"{"length":120,"time":100}"#);
+
+        let controller = testing::controller(&[], silent_vlc());
+        assert_eq!(testing::run(&controller, "get_progress").await.unwrap(), r#"{"length":null,"time":null}"#);
+    }
+
+    #[tokio::test]
+    async fn snapshot_without_a_dir_says_the_path_is_unknown() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&[], vlc.clone());
+        assert_eq!(testing::run(&controller, "snapshot").await.unwrap(), snapshot::UNKNOWN_PATH);
+        assert_eq!(vlc.sent(), ["snapshot"]);
+    }
+
+    #[tokio::test]
+    async fn seek_pct_validates_and_rounds() {
+        let vlc = MockVlc::new(|command| match command {
+            "get_length" => Ok("634".to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = testing::controller(&[], vlc.clone());
+        assert_eq!(testing::run(&controller, "seek_pct 50.1").await.unwrap(), "318");
+        assert_eq!(vlc.sent().last().unwrap(), "seek 318");
+        assert!(testing::run(&controller, "seek_pct 101").await.is_err());
+        assert!(testing::run(&controller, "seek_pct half").await.is_err());
+
+        // Live streams report a length of 0
+        let vlc = MockVlc::new(|command| match command {
+            "get_length" => Ok("0".to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = testing::controller(&[], vlc.clone());
+        let err = testing::run(&controller, "seek_pct 50").await.unwrap_err();
+        assert!(err.to_string().contains("no length"), "{}", err);
+        assert!(!vlc.sent().iter().any(|c| c.starts_with("seek")));
+    }
+
+    #[tokio::test]
+    async fn aspect_and_crop_validate_before_forwarding() {
+        let vlc = MockVlc::new(|command| match command {
+            "vratio" => Ok("+----[ aspect-ratio ]\n|  - Default\n| 4:3 - 4:3 *\n+----[ end of aspect-ratio ]".to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = testing::controller(&[], vlc.clone());
+        for command in ["aspect 16:9", "aspect 2.35:1", "crop 16:10", "crop 1280x720+0+40"] {
+            testing::run(&controller, command).await.unwrap();
+        }
+        for command in ["aspect 16/9", "aspect 0:1", "aspect 16:", "crop 1280x0+0+0", "crop 1280x720+0", "crop 12x7+-1+0"] {
+            assert!(testing::run(&controller, command).await.is_err(), "{}", command);
+        }
+        assert_eq!(vlc.sent(), ["vratio 16:9", "vratio 2.35:1", "vcrop 16:10", "vcrop 1280x720+0+40"]);
+        assert_eq!(testing::run(&controller, "get_aspect").await.unwrap(), "4:3");
+    }
+
+    #[tokio::test]
+    async fn get_volume_is_a_percentage() {
+        let vlc = MockVlc::new(|_| Ok("( audio volume: 320 )".to_string()));
+        let controller = testing::controller(&[], vlc);
+        assert_eq!(testing::run(&controller, "get_volume").await.unwrap(), "125");
+        assert_eq!(volume_percent(128), 50);
+        assert_eq!(volume_percent(0), 0);
+    }
+
+    #[tokio::test]
+    async fn concurrent_identical_queries_share_a_forward() {
+        let vlc = MockVlc::new(|_| Ok("( state playing )".to_string()));
+        let controller = testing::controller(&["--coalesce-queries"], vlc.clone());
+        // The mock answers at once, so hold the semaphore to keep the first query in flight.
+        let permit = controller.exclusive_vlc_access().await.unwrap();
+        let queries = tokio::spawn({
+            let controller = controller.clone();
+            async move { tokio::join!(testing::run(&controller, "status"), testing::run(&controller, "status")) }
+        });
+        tokio::task::yield_now().await;
+        drop(permit);
+        let (first, second) = queries.await.unwrap();
+        assert_eq!(first.unwrap(), second.unwrap());
+        assert_eq!(vlc.sent(), ["status"]);
+    }
+
+    #[tokio::test]
+    async fn rate_commands_stay_in_range() {
+        let vlc = MockVlc::new(|command| match command {
+            "rate" => Ok("3.90".to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = testing::controller(&[], vlc.clone());
+        assert_eq!(testing::run(&controller, "rate_up").await.unwrap(), "4");
+        assert_eq!(vlc.sent().last().unwrap(), "rate 4");
+        assert_eq!(testing::run(&controller, "rate_down 0.4").await.unwrap(), "3.5");
+        assert!(testing::run(&controller, "rate_set 8").await.is_err());
+        assert_eq!(vlc.sent().last().unwrap(), "rate 3.5");
+    }
+
+    #[tokio::test]
+    async fn restart_item_needs_media() {
+        let vlc = MockVlc::new(|command| match command {
+            "status" => Ok("( audio volume: 256 )\r\n( state stopped )".to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = testing::controller(&[], vlc.clone());
+        let err = testing::run(&controller, "restart_item").await.unwrap_err();
+        assert!(err.to_string().starts_with("No media playing"));
+        assert_eq!(vlc.sent(), ["status"]);
+    }
+
+    #[tokio::test]
+    async fn restart_item_seeks_once_playback_resumes() {
+        let polls = std::sync::atomic::AtomicU32::new(0);
+        let vlc = MockVlc::new(move |command| match command {
+            "status" if polls.fetch_add(1, std::sync::atomic::Ordering::Relaxed) < 2 => {
+                Ok("( new input: file:///media/a.mp4 )\r\n( state paused )".to_string())
+            }
+            "status" => Ok("( new input: file:///media/a.mp4 )\r\n( state playing )".to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = testing::controller(&[], vlc.clone());
+        assert_eq!(testing::run(&controller, "restart_item").await.unwrap(), "file:///media/a.mp4");
+        assert_eq!(vlc.sent(), ["status", "play", "status", "status", "seek 0"]);
+    }
+
+    #[tokio::test]
+    async fn swap_to_seeks_once_the_new_media_plays() {
+        let polls = std::sync::atomic::AtomicU32::new(0);
+        let vlc = MockVlc::new(move |command| match command {
+            "status" => Ok(match polls.fetch_add(1, std::sync::atomic::Ordering::Relaxed) {
+                // The old item may still report playing before VLC opens the new one.
+                0 => "( new input: file:///media/a.mp4 )\r\n( state playing )",
+                1 => "( state opening )",
+                _ => "( new input: file:///media/b.mp4 )\r\n( state playing )",
+            }
+            .to_string()),
+            _ => Ok(String::new()),
+        });
+        let controller = testing::controller(&[], vlc.clone());
+        for command in ["swap_to", "swap_to b.mp4", "swap_to file:///media/b.mp4 soon", "swap_to /media/b.mp4 1 2"] {
+            assert!(testing::run(&controller, command).await.is_err(), "{}", command);
+        }
+        assert!(vlc.sent().is_empty());
+
+        let input = testing::run(&controller, "swap_to file:///media/b.mp4 42").await.unwrap();
+        assert_eq!(input, "file:///media/b.mp4");
+        assert_eq!(vlc.sent(), ["stop", "add file:///media/b.mp4", "status", "status", "status", "seek 42"]);
+
+        assert!(same_media("file:///media/b.mp4", "/media/b.mp4"));
+        assert!(same_media("file:///media/%C3%BC%20b.mp4", "file:///media/ü%20b.mp4"));
+        assert!(!same_media("file:///media/a.mp4", "/media/b.mp4"));
+    }
+
+    #[tokio::test]
+    async fn is_playing_reads_the_status() {
+        let vlc = MockVlc::new(|_| Ok("( state paused )".to_string()));
+        let controller = testing::controller(&[], vlc.clone());
+        assert_eq!(testing::run(&controller, "is_playing").await.unwrap(), "false");
+        assert_eq!(vlc.sent(), ["status"]);
+    }
+
+    #[tokio::test]
+    async fn expect_checks_the_response() {
+        let vlc = MockVlc::new(|_| Ok("( state playing )".to_string()));
+        let controller = testing::controller(&[], vlc);
+        assert!(testing::run(&controller, "expect state playing :: status").await.is_ok());
+        let err = testing::run(&controller, "expect state paused :: status").await.unwrap_err();
+        assert!(err.to_string().starts_with("FAIL"));
+    }
+
+    #[tokio::test]
+    async fn get_meta_from_recorded_sessions() {
+        for (name, text) in testing::FIXTURES {
+            let addr = testing::replay(testing::Transcript::parse(text)).await;
+            let vlc = Arc::new(VlcClient::new(addr.to_string(), VlcOptions::default()));
+            let controller = testing::controller(&[], vlc);
+            let meta: serde_json::Value = serde_json::from_str(&testing::run(&controller, "get_meta").await.unwrap()).unwrap();
+            assert!(meta["meta"]["title"].is_string(), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_vlc_responses_are_refused() {
+        let (_, text) = testing::FIXTURES[0];
+        let addr = testing::replay(testing::Transcript::parse(text)).await;
+        let options = VlcOptions {
+            max_response_bytes: Some(200),
+            ..VlcOptions::default()
+        };
+        let vlc = Arc::new(VlcClient::new(addr.to_string(), options));
+        let controller = testing::controller(&[], vlc);
+        assert!(testing::run(&controller, "get_time").await.is_ok());
+        let err = testing::run(&controller, "info").await.unwrap_err();
+        assert!(err.to_string().contains("exceeds 200 bytes"));
+    }
+
+    #[tokio::test]
+    async fn vlc_stats_report_no_persistent_connection_per_command() {
+        let controller = testing::controller(&[], silent_vlc());
+        let stats: serde_json::Value = serde_json::from_str(&testing::run(&controller, "pi_vlc_stats").await.unwrap()).unwrap();
+        assert_eq!(stats["persistent"], false);
+        assert_eq!(stats["reconnects"], 0);
+        assert!(stats["connection_age_secs"].is_null());
+    }
+
+    #[tokio::test]
+    async fn vlc_reconnect_says_when_there_is_nothing_to_reconnect() {
+        let vlc = silent_vlc();
+        let controller = testing::controller(&[], vlc.clone());
+        let reply = testing::run(&controller, "pi_vlc_reconnect").await.unwrap();
+        assert_eq!(reply, "no persistent connection, nothing to reconnect");
+        assert!(vlc.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn diag_reports_the_last_connection() {
+        let (_, text) = testing::FIXTURES[1];
+        let addr = testing::replay(testing::Transcript::parse(text)).await;
+        let options = VlcOptions {
+            retry_budget: Some(Arc::new(RetryBudget::new(5, Duration::from_secs(60)))),
+            ..VlcOptions::default()
+        };
+        let controller = testing::controller(&[], Arc::new(VlcClient::new(addr.to_string(), options)));
+        let diag: serde_json::Value = serde_json::from_str(&testing::run(&controller, "pi_diag").await.unwrap()).unwrap();
+        assert!(diag["last_connect"].is_null());
+
+        testing::run(&controller, "get_time").await.unwrap();
+        let diag: serde_json::Value = serde_json::from_str(&testing::run(&controller, "pi_diag").await.unwrap()).unwrap();
+        assert_eq!(diag["last_connect"]["ok"], true);
+        assert_eq!(diag["retry_budget_remaining"], 5);
+        assert_eq!(diag["circuit"]["state"], "closed");
+    }
+
+    #[tokio::test]
+    async fn closing_commands_read_until_eof() {
+        let (_, text) = testing::FIXTURES[1];
+        let addr = testing::replay(testing::Transcript::parse(text)).await;
+        let vlc = Arc::new(VlcClient::new(addr.to_string(), VlcOptions::default()));
+        let controller = testing::controller(&[], vlc);
+        assert_eq!(testing::run(&controller, "logout").await.unwrap(), "Bye-bye!");
+    }
+
+    #[tokio::test]
+    async fn vlc_errors_reach_the_client() {
+        let vlc = MockVlc::new(|_| Err(anyhow::anyhow!("Connection refused")));
+        let controller = testing::controller(&[], vlc);
+        let result = testing::run(&controller, "play").await;
+        assert_eq!(format_reply(&result), "ERR Connection refused\n");
+    }
 }
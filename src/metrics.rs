@@ -0,0 +1,188 @@
+//! Counters exposed in the Prometheus text format on `--metrics-address`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::Transport;
+use crate::breaker::CircuitStatus;
+use crate::vlc::ConnectionStats;
+
+/// Upper bounds (seconds) of the connection duration histogram buckets.
+const DURATION_BUCKETS: &[f64] = &[0.1, 1.0, 10.0, 60.0, 300.0, 1800.0, 3600.0];
+
+#[derive(Default)]
+pub struct Metrics {
+    tcp_connections_active: AtomicU64,
+    tcp_connections_total: AtomicU64,
+    tcp_connection_duration: Histogram,
+    /// Per transport (in `Transport::ALL` order): succeeded, failed
+    commands: [[AtomicU64; 2]; Transport::ALL.len()],
+    /// Refused connections and allowlist refusals, by rejection code
+    rejections: Mutex<BTreeMap<&'static str, u64>>,
+    /// Programs run by management commands, by command and exit code
+    system_commands: Mutex<BTreeMap<(&'static str, String), u64>>,
+}
+
+/// Cumulative histogram in the Prometheus sense.
+struct Histogram {
+    bounds: &'static [f64],
+    /// One counter per bound, plus the implicit `+Inf` bucket at the end
+    buckets: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            bounds: DURATION_BUCKETS,
+            buckets: (0..=DURATION_BUCKETS.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&self, value: Duration) {
+        let secs = value.as_secs_f64();
+        let bucket = self.bounds.iter().position(|&bound| secs <= bound).unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = self.bounds.get(i).map(|bound| bound.to_string()).unwrap_or_else(|| "+Inf".to_string());
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count.load(Ordering::Relaxed));
+    }
+}
+
+impl Metrics {
+    pub fn tcp_connection_opened(&self) {
+        self.tcp_connections_total.fetch_add(1, Ordering::Relaxed);
+        self.tcp_connections_active.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn tcp_connection_closed(&self, duration: Duration) {
+        self.tcp_connections_active.fetch_sub(1, Ordering::Relaxed);
+        self.tcp_connection_duration.observe(duration);
+    }
+
+    pub fn tcp_connections_active(&self) -> u64 {
+        self.tcp_connections_active.load(Ordering::Relaxed)
+    }
+
+    pub fn command_processed(&self, transport: Transport, ok: bool) {
+        let index = Transport::ALL.iter().position(|&t| t == transport).unwrap_or_default();
+        self.commands[index][usize::from(!ok)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected(&self, reason: &'static str) {
+        *self.rejections.lock().unwrap().entry(reason).or_default() += 1;
+    }
+
+    /// Counts a program run by the management command `command`. The exit
+    /// code is `none` if it could not be started or was killed by a signal.
+    pub fn system_command(&self, command: &'static str, status: &std::io::Result<ExitStatus>) {
+        let exit_code = match status.as_ref().map(ExitStatus::code) {
+            Ok(Some(code)) => code.to_string(),
+            _ => "none".to_string(),
+        };
+        *self.system_commands.lock().unwrap().entry((command, exit_code)).or_default() += 1;
+    }
+
+    /// Renders every metric, plus the circuit breaker state and the VLC
+    /// connection's churn, in the Prometheus text exposition format.
+    pub fn render(&self, circuit: &CircuitStatus, vlc: &ConnectionStats) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP vlc_control_tcp_connections_active Currently open TCP client connections.");
+        let _ = writeln!(out, "# TYPE vlc_control_tcp_connections_active gauge");
+        let _ = writeln!(
+            out,
+            "vlc_control_tcp_connections_active {}",
+            self.tcp_connections_active.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(out, "# HELP vlc_control_tcp_connections_total TCP client connections accepted.");
+        let _ = writeln!(out, "# TYPE vlc_control_tcp_connections_total counter");
+        let _ = writeln!(
+            out,
+            "vlc_control_tcp_connections_total {}",
+            self.tcp_connections_total.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(out, "# HELP vlc_control_tcp_connection_duration_seconds How long TCP clients stay connected.");
+        let _ = writeln!(out, "# TYPE vlc_control_tcp_connection_duration_seconds histogram");
+        self.tcp_connection_duration.render(&mut out, "vlc_control_tcp_connection_duration_seconds");
+
+        let _ = writeln!(out, "# HELP vlc_control_commands_total Client commands processed, by transport and outcome.");
+        let _ = writeln!(out, "# TYPE vlc_control_commands_total counter");
+        for (transport, counts) in Transport::ALL.iter().zip(&self.commands) {
+            for (outcome, count) in ["ok", "error"].iter().zip(counts) {
+                let _ = writeln!(
+                    out,
+                    "vlc_control_commands_total{{transport=\"{}\",outcome=\"{}\"}} {}",
+                    transport.as_str(),
+                    outcome,
+                    count.load(Ordering::Relaxed)
+                );
+            }
+        }
+
+        let _ = writeln!(out, "# HELP vlc_control_rejections_total Refused connections and commands refused by the allowlist, by reason.");
+        let _ = writeln!(out, "# TYPE vlc_control_rejections_total counter");
+        for (reason, count) in self.rejections.lock().unwrap().iter() {
+            let _ = writeln!(out, "vlc_control_rejections_total{{reason=\"{}\"}} {}", reason, count);
+        }
+
+        let _ = writeln!(out, "# HELP vlc_control_system_commands_total Programs run by management commands, by command and exit code.");
+        let _ = writeln!(out, "# TYPE vlc_control_system_commands_total counter");
+        for ((command, exit_code), count) in self.system_commands.lock().unwrap().iter() {
+            let outcome = if exit_code == "0" { "success" } else { "failure" };
+            let _ = writeln!(
+                out,
+                "vlc_control_system_commands_total{{command=\"{}\",outcome=\"{}\",exit_code=\"{}\"}} {}",
+                command, outcome, exit_code, count
+            );
+        }
+
+        let _ = writeln!(out, "# HELP vlc_control_vlc_circuit_state VLC circuit breaker: 0 closed, 1 half-open, 2 open.");
+        let _ = writeln!(out, "# TYPE vlc_control_vlc_circuit_state gauge");
+        let _ = writeln!(out, "vlc_control_vlc_circuit_state {}", circuit.state.gauge());
+
+        let _ = writeln!(out, "# HELP vlc_control_vlc_circuit_opens_total Times the VLC circuit breaker opened.");
+        let _ = writeln!(out, "# TYPE vlc_control_vlc_circuit_opens_total counter");
+        let _ = writeln!(out, "vlc_control_vlc_circuit_opens_total {}", circuit.opens);
+
+        if vlc.persistent {
+            let _ = writeln!(out, "# HELP vlc_control_vlc_reconnects_total Times the persistent VLC connection was replaced.");
+            let _ = writeln!(out, "# TYPE vlc_control_vlc_reconnects_total counter");
+            let _ = writeln!(out, "vlc_control_vlc_reconnects_total {}", vlc.reconnects);
+            if let Some(timestamp_ms) = vlc.last_reconnect_timestamp_ms {
+                let _ = writeln!(out, "# HELP vlc_control_vlc_last_reconnect_timestamp_seconds When the VLC connection was last replaced.");
+                let _ = writeln!(out, "# TYPE vlc_control_vlc_last_reconnect_timestamp_seconds gauge");
+                let _ = writeln!(out, "vlc_control_vlc_last_reconnect_timestamp_seconds {}", timestamp_ms as f64 / 1000.0);
+            }
+            if let Some(age) = vlc.connection_age_secs {
+                let _ = writeln!(out, "# HELP vlc_control_vlc_connection_age_seconds How long the current VLC connection has been open.");
+                let _ = writeln!(out, "# TYPE vlc_control_vlc_connection_age_seconds gauge");
+                let _ = writeln!(out, "vlc_control_vlc_connection_age_seconds {}", age);
+            }
+        }
+
+        out
+    }
+}
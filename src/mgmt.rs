@@ -0,0 +1,235 @@
+//! Management commands, which act on the controller or the host instead of
+//! VLC. Clients address them as `<mgmt prefix><name>`, e.g. `pi_status` with
+//! the default `pi_` prefix.
+
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::process::ExitStatus;
+use tokio::process::Command;
+use tracing::{error, info, warn};
+
+use crate::logging::LogFilter;
+use crate::{Controller, LogLevel};
+use crate::breaker::CircuitStatus;
+use crate::commands::Mutability;
+use crate::history::HISTORY_CAPACITY;
+use crate::vlc::VlcDiagnostics;
+use crate::watchdog::WatchdogStatus;
+
+const DEFAULT_HISTORY_COUNT: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MgmtCommand {
+    RestartVlc,
+    Shutdown,
+    Reboot,
+    Status,
+    History,
+    Quit,
+    Diag,
+    Backends,
+    VlcReconnect,
+    VlcStats,
+    SetLogLevel,
+    ResetLogLevel,
+}
+
+/// Every management command by name, without the prefix. Only these can be
+/// run; anything else under the prefix is rejected.
+pub const REGISTRY: &[(&str, MgmtCommand)] = &[
+    ("restart_vlc", MgmtCommand::RestartVlc),
+    ("shutdown", MgmtCommand::Shutdown),
+    ("reboot", MgmtCommand::Reboot),
+    ("status", MgmtCommand::Status),
+    ("history", MgmtCommand::History),
+    ("quit", MgmtCommand::Quit),
+    ("diag", MgmtCommand::Diag),
+    ("backends", MgmtCommand::Backends),
+    ("vlc_reconnect", MgmtCommand::VlcReconnect),
+    ("vlc_stats", MgmtCommand::VlcStats),
+    ("set_log_level", MgmtCommand::SetLogLevel),
+    ("reset_log_level", MgmtCommand::ResetLogLevel),
+];
+
+pub fn lookup(name: &str) -> Option<MgmtCommand> {
+    REGISTRY.iter().find(|(registered, _)| *registered == name).map(|(_, command)| *command)
+}
+
+impl MgmtCommand {
+    /// Registered name, without the prefix.
+    pub fn name(self) -> &'static str {
+        REGISTRY.iter().find(|(_, command)| *command == self).map(|(name, _)| *name).unwrap_or_default()
+    }
+
+    /// Arguments accepted, for `list_commands`.
+    pub fn usage(self) -> &'static str {
+        match self {
+            MgmtCommand::History => "[count]",
+            MgmtCommand::SetLogLevel => "<error|warn|info|debug|trace>",
+            _ => "",
+        }
+    }
+
+    /// Whether `--read-only` refuses it.
+    pub fn mutability(self) -> Mutability {
+        match self {
+            MgmtCommand::Status
+            | MgmtCommand::History
+            | MgmtCommand::Diag
+            | MgmtCommand::Backends
+            | MgmtCommand::VlcStats => Mutability::ReadOnly,
+            _ => Mutability::Mutating,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            MgmtCommand::RestartVlc => "Restart the VLC service",
+            MgmtCommand::Shutdown => "Power off the host",
+            MgmtCommand::Reboot => "Reboot the host",
+            MgmtCommand::Status => "Controller status as JSON",
+            MgmtCommand::History => "Most recent commands as JSON",
+            MgmtCommand::Quit => "Stop the controller gracefully; unlike shutdown, the host stays up",
+            MgmtCommand::Diag => "Connection diagnostics (VLC reachability, circuit, clients) as JSON",
+            MgmtCommand::Backends => "Reachability, command counts and last contact of each VLC backend as JSON",
+            MgmtCommand::VlcReconnect => "Restart the VLC of --vlc-protocol stdin; other modes connect per command and have nothing to reconnect",
+            MgmtCommand::VlcStats => "Reconnects and age of the persistent VLC connection (--vlc-protocol stdin) as JSON",
+            MgmtCommand::SetLogLevel => "Change the controller's log level until reset_log_level or restart",
+            MgmtCommand::ResetLogLevel => "Return to the log level the controller was started with",
+        }
+    }
+}
+
+/// Snapshot of the controller state returned by the `status` command.
+#[derive(Serialize)]
+struct ControllerStatus<'a> {
+    vlc_address: &'a str,
+    watchdog: WatchdogStatus,
+    circuit: CircuitStatus,
+}
+
+/// Connection diagnostics returned by the `diag` command.
+#[derive(Serialize)]
+struct Diagnostics<'a> {
+    vlc_address: &'a str,
+    #[serde(flatten)]
+    vlc: VlcDiagnostics,
+    circuit: CircuitStatus,
+    tcp_connections_active: u64,
+}
+
+/// Runs a management command, returning the response for the client.
+pub async fn run(command: MgmtCommand, args: &str, controller: &Controller) -> Result<String> {
+    if !matches!(command, MgmtCommand::History | MgmtCommand::SetLogLevel) && !args.is_empty() {
+        bail!("{} takes no arguments", command.name());
+    }
+
+    let mut response = String::new();
+    match command {
+        MgmtCommand::RestartVlc => {
+            info!("Executing VLC restart command");
+            let status = restart_vlc().await;
+            controller.metrics.system_command(command.name(), &status);
+            status?;
+        }
+        MgmtCommand::Status => {
+            let status = ControllerStatus {
+                vlc_address: controller.vlc.addr(),
+                watchdog: controller.watchdog.status(),
+                circuit: controller.breaker.status(),
+            };
+            response = serde_json::to_string(&status)?;
+        }
+        MgmtCommand::Diag => {
+            let diagnostics = Diagnostics {
+                vlc_address: controller.vlc.addr(),
+                vlc: controller.vlc.diagnostics(),
+                circuit: controller.breaker.status(),
+                tcp_connections_active: controller.metrics.tcp_connections_active(),
+            };
+            response = serde_json::to_string(&diagnostics)?;
+        }
+        MgmtCommand::Backends => {
+            response = serde_json::to_string(&controller.vlc.backends())?;
+        }
+        MgmtCommand::VlcReconnect => {
+            response = match controller.vlc.reconnect().await? {
+                Some(connection) => {
+                    info!(connection = %connection, "Reconnected to VLC");
+                    connection
+                }
+                None => "no persistent connection, nothing to reconnect".to_string(),
+            };
+        }
+        MgmtCommand::VlcStats => {
+            response = serde_json::to_string(&controller.vlc.connection_stats())?;
+        }
+        MgmtCommand::History => {
+            let count = match args {
+                "" => DEFAULT_HISTORY_COUNT,
+                count => count.parse::<usize>().map_err(|_| anyhow::anyhow!("Invalid history count: {}", count))?,
+            };
+            response = serde_json::to_string(&controller.history.recent(count.min(HISTORY_CAPACITY)))?;
+        }
+        MgmtCommand::SetLogLevel => {
+            let level = LogLevel::from_str(args, true).map_err(|_| {
+                anyhow::anyhow!("Invalid log level: '{}' (expected error, warn, info, debug or trace)", args)
+            })?;
+            log_filter(controller)?.set(level)?;
+        }
+        MgmtCommand::ResetLogLevel => log_filter(controller)?.reset()?,
+        MgmtCommand::Quit => {
+            // Same path as SIGTERM: in-flight commands, including this one, drain first.
+            warn!("Executing controller quit command (not a host shutdown)");
+            controller.shutdown.request_quit();
+        }
+        MgmtCommand::Shutdown => {
+            // Let VLC commands queued before this one run first.
+            let _permit = controller.exclusive_vlc_access().await?;
+            warn!("Executing system shutdown command (powering off the host)");
+            let status = controller.privilege.command(&["shutdown", "-h", "now"]).status();
+            controller.metrics.system_command(command.name(), &status);
+            let status = status?;
+            if status.success() {
+                info!("Shutdown command completed successfully");
+            } else {
+                error!(exit_code = status.code(), "Shutdown command failed");
+            }
+        }
+        MgmtCommand::Reboot => {
+            let _permit = controller.exclusive_vlc_access().await?;
+            warn!("Executing system reboot command");
+            let status = controller.privilege.command(&["shutdown", "-r", "now"]).status();
+            controller.metrics.system_command(command.name(), &status);
+            let status = status?;
+            if status.success() {
+                info!("Reboot command completed successfully");
+            } else {
+                error!(exit_code = status.code(), "Reboot command failed");
+            }
+        }
+    }
+    Ok(response)
+}
+
+fn log_filter(controller: &Controller) -> Result<&LogFilter> {
+    controller.log_filter.as_ref().ok_or_else(|| anyhow::anyhow!("Log level cannot be changed at runtime"))
+}
+
+/// Restarts the VLC service, used by `restart_vlc` and the watchdog.
+///
+/// `systemctl restart` can take a while, so it is awaited rather than
+/// blocking a runtime worker.
+pub async fn restart_vlc() -> std::io::Result<ExitStatus> {
+    let status = Command::new("systemctl")
+        .args(["--user", "restart", "vlc-loader.service"])
+        .status()
+        .await?;
+    if status.success() {
+        info!("VLC restart command completed successfully");
+    } else {
+        warn!(exit_code = status.code(), "VLC restart command failed");
+    }
+    Ok(status)
+}
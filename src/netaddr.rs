@@ -0,0 +1,149 @@
+//! IPv6 zone (scope) identifiers, as in `fe80::1%eth0`.
+//!
+//! Sockets report the zone of a link-local peer as a numeric scope ID; the
+//! helpers here translate between that and interface names so allowlists can
+//! say `fe80::%eth0/64` and logs show `[fe80::1%eth0]:5000`. Also the port
+//! ranges of `--udp-source-ports`.
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// Resolves a zone given as an interface name or a numeric index.
+pub fn zone_index(zone: &str) -> Result<u32> {
+    if let Ok(index) = zone.parse::<u32>() {
+        return Ok(index);
+    }
+    interface_index(zone).with_context(|| format!("unknown network interface '{}'", zone))
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: `name` is a valid NUL-terminated string for the duration of the call.
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+/// Interface name of a scope ID, falling back to the number.
+pub fn zone_name(index: u32) -> String {
+    interface_name(index).unwrap_or_else(|| index.to_string())
+}
+
+#[cfg(unix)]
+fn interface_name(index: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    // SAFETY: `buf` has the IF_NAMESIZE bytes if_indextoname may write.
+    let name = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+    // SAFETY: on success `buf` holds a NUL-terminated name.
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn interface_name(_index: u32) -> Option<String> {
+    None
+}
+
+/// Displays a client address with the interface name as its zone, e.g.
+/// `[fe80::1%eth0]:5000`; other addresses display as usual.
+pub struct ClientAddr(pub SocketAddr);
+
+impl fmt::Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            SocketAddr::V6(addr) if addr.scope_id() != 0 => {
+                write!(f, "[{}%{}]:{}", addr.ip(), zone_name(addr.scope_id()), addr.port())
+            }
+            addr => addr.fmt(f),
+        }
+    }
+}
+
+/// The IP of a client address with its zone, e.g. `fe80::1%eth0`.
+pub fn display_ip(addr: SocketAddr) -> String {
+    match addr {
+        SocketAddr::V6(addr) if addr.scope_id() != 0 => format!("{}%{}", addr.ip(), zone_name(addr.scope_id())),
+        addr => addr.ip().to_string(),
+    }
+}
+
+/// Inclusive range of ports, written `4000-4100` or as a single port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortRange {
+    start: u16,
+    end: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = anyhow::Error;
+
+    fn from_str(range: &str) -> Result<Self> {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let port = |port: &str| port.trim().parse::<u16>().with_context(|| format!("invalid port '{}'", port.trim()));
+        let (start, end) = (port(start)?, port(end)?);
+        if start > end {
+            anyhow::bail!("port range {} starts after it ends", range);
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numeric_zones_need_no_interface() {
+        assert_eq!(zone_index("7").unwrap(), 7);
+        assert!(zone_index("no-such-interface0").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn loopback_zone_round_trips() {
+        let index = zone_index("lo").unwrap();
+        assert_eq!(zone_name(index), "lo");
+        let addr: SocketAddr = format!("[fe80::1%{}]:5000", index).parse().unwrap();
+        assert_eq!(ClientAddr(addr).to_string(), "[fe80::1%lo]:5000");
+        assert_eq!(display_ip(addr), "fe80::1%lo");
+    }
+
+    #[test]
+    fn unscoped_addresses_display_as_usual() {
+        let addr: SocketAddr = "[2001:db8::1]:5000".parse().unwrap();
+        assert_eq!(ClientAddr(addr).to_string(), "[2001:db8::1]:5000");
+        assert_eq!(display_ip("192.0.2.1:5000".parse().unwrap()), "192.0.2.1");
+    }
+
+    #[test]
+    fn parses_port_ranges() {
+        let range: PortRange = "4000-4100".parse().unwrap();
+        assert!(range.contains(4000) && range.contains(4100) && !range.contains(4101));
+        assert_eq!("5000".parse::<PortRange>().unwrap().to_string(), "5000-5000");
+        for invalid in ["4100-4000", "4000-", "70000", "a-b"] {
+            assert!(invalid.parse::<PortRange>().is_err(), "{}", invalid);
+        }
+    }
+}
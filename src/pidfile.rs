@@ -0,0 +1,56 @@
+//! `--pid-file`: records the process ID for init scripts.
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// What to do when the PID file names a process that is still running.
+#[derive(Copy, Clone, Default, PartialEq, Eq, ValueEnum)]
+pub enum PidFileConflict {
+    /// Refuse to start
+    #[default]
+    Refuse,
+    /// Log a warning and take the file over
+    Warn,
+}
+
+/// The written PID file, removed again when dropped at shutdown.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path, on_conflict: PidFileConflict) -> Result<Self> {
+        if let Some(pid) = running_pid(path) {
+            match on_conflict {
+                PidFileConflict::Refuse => {
+                    bail!("{} names running process {}; is another instance running?", path.display(), pid)
+                }
+                PidFileConflict::Warn => {
+                    warn!(path = %path.display(), pid = pid, "PID file names a running process, overwriting it");
+                }
+            }
+        }
+
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Failed to write PID file {}", path.display()))?;
+        info!(path = %path.display(), pid = std::process::id(), "Wrote PID file");
+        Ok(Self { path: path.to_path_buf() })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!(path = %self.path.display(), error = %e, "Failed to remove PID file");
+        }
+    }
+}
+
+/// The PID in an existing file, if that process is alive (checked via `/proc`).
+fn running_pid(path: &Path) -> Option<u32> {
+    let pid: u32 = std::fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    let alive = pid != std::process::id() && Path::new(&format!("/proc/{}", pid)).exists();
+    alive.then_some(pid)
+}
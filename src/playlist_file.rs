@@ -0,0 +1,98 @@
+//! The `load_playlist` command: replace VLC's playlist with an `.m3u`,
+//! `.m3u8`, `.pls` or `.xspf` file, optionally confined to `--playlist-dir`.
+
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::{Controller, rc};
+
+const EXTENSIONS: &[&str] = &["m3u", "m3u8", "pls", "xspf"];
+
+/// Fails unless `dir` is a directory.
+pub fn check_dir(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        bail!("--playlist-dir {} is not a directory", dir.display());
+    }
+    Ok(())
+}
+
+/// Resolves the client's `path` to an existing playlist file. With `base`,
+/// relative paths are taken from it and the file must lie inside it (after
+/// following symlinks); without, the path must be absolute.
+pub fn resolve(path: &str, base: Option<&Path>) -> Result<PathBuf> {
+    let requested = Path::new(path);
+    let joined = match base {
+        Some(base) => base.join(requested),
+        None if requested.is_absolute() => requested.to_path_buf(),
+        None => bail!("Playlist path must be absolute: {}", path),
+    };
+    let resolved = joined.canonicalize().with_context(|| format!("No such playlist: {}", path))?;
+    if let Some(base) = base {
+        let base = base.canonicalize().with_context(|| format!("--playlist-dir {}", base.display()))?;
+        if !resolved.starts_with(&base) {
+            bail!("Playlist {} is outside the playlist directory", path);
+        }
+    }
+    if !resolved.is_file() {
+        bail!("Playlist {} is not a file", path);
+    }
+    let extension = resolved.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase();
+    if !EXTENSIONS.contains(&extension.as_str()) {
+        bail!("Not a playlist file: {} (expected {})", path, EXTENSIONS.join(", "));
+    }
+    Ok(resolved)
+}
+
+/// Clears the playlist, adds `file` (which VLC expands and starts playing)
+/// and returns the number of items VLC then lists.
+pub async fn load(controller: &Controller, file: &Path) -> Result<String> {
+    controller.forward(b"clear\n").await?;
+    controller.forward(format!("add {}\n", file_uri(file)).as_bytes()).await?;
+
+    let items = rc::parse_playlist(&controller.forward(b"playlist\n").await?);
+    if items.is_empty() {
+        bail!("VLC lists no items after loading {}", file.display());
+    }
+    let status = rc::parse_status(&controller.forward(b"status\n").await?);
+    info!(path = %file.display(), items = items.len(), input = status.input.as_deref(), "Loaded playlist");
+    Ok(items.len().to_string())
+}
+
+/// `file://` URI of an absolute path, percent-encoding everything but
+/// unreserved characters and separators.
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confines_playlists_to_the_base() {
+        let base = std::env::temp_dir().join(format!("vlc-control-playlists-{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(base.join("show.m3u"), "#EXTM3U\n").unwrap();
+        std::fs::write(base.join("notes.txt"), "").unwrap();
+
+        assert_eq!(resolve("show.m3u", Some(&base)).unwrap(), base.join("show.m3u").canonicalize().unwrap());
+        assert!(resolve("../show.m3u", Some(&base)).is_err());
+        assert!(resolve("missing.m3u", Some(&base)).is_err());
+        assert!(resolve("notes.txt", Some(&base)).is_err());
+        assert!(resolve("show.m3u", None).is_err());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn encodes_file_uris() {
+        assert_eq!(file_uri(Path::new("/media/My Show #1.m3u")), "file:///media/My%20Show%20%231.m3u");
+    }
+}
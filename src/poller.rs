@@ -0,0 +1,195 @@
+//! Background status polling for what RC cannot express directly:
+//!
+//! - `stop_after_current`: VLC has no "stop at the end of this item", so the
+//!   poller watches for the input to change and then stops or pauses.
+//! - `loop_range`: VLC only loops the whole playlist, so the poller jumps back
+//!   to the first item of the range once playback leaves it.
+
+use anyhow::{Result, bail};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+use crate::{Controller, rc};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What to do once the current item has finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndAction {
+    Stop,
+    Pause,
+}
+
+impl EndAction {
+    fn command(self) -> &'static [u8] {
+        match self {
+            EndAction::Stop => b"stop\n",
+            EndAction::Pause => b"pause\n",
+        }
+    }
+}
+
+struct Pending {
+    /// Input that was current when armed
+    input: String,
+    action: EndAction,
+}
+
+/// Shared between the command handler and the polling task.
+#[derive(Default)]
+pub struct StopAfterCurrent {
+    pending: Mutex<Option<Pending>>,
+    armed: Notify,
+}
+
+impl StopAfterCurrent {
+    /// Cancels a pending stop, returning true if there was one.
+    pub fn disarm(&self) -> bool {
+        self.pending.lock().unwrap().take().is_some()
+    }
+}
+
+/// The playlist positions `loop_range` keeps playback within, from 1, inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Range {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Shared between the command handler and the polling task.
+#[derive(Default)]
+pub struct LoopRange {
+    range: Mutex<Option<Range>>,
+    armed: Notify,
+}
+
+impl LoopRange {
+    /// Stops looping, returning true if a range was set.
+    pub fn clear(&self) -> bool {
+        self.range.lock().unwrap().take().is_some()
+    }
+}
+
+/// Plays the first item of `range` and keeps playback within it until cleared.
+pub async fn loop_range(controller: &Controller, range: Range) -> Result<()> {
+    let items = rc::parse_playlist(&controller.forward(b"playlist\n").await?);
+    if range.end > items.len() {
+        bail!("No playlist item at position {}, the playlist has {} item(s)", range.end, items.len());
+    }
+    controller.forward(format!("goto {}\n", range.start).as_bytes()).await?;
+
+    let state = &controller.loop_range;
+    *state.range.lock().unwrap() = Some(range);
+    state.armed.notify_one();
+    info!(start = range.start, end = range.end, "Looping playlist range");
+    Ok(())
+}
+
+/// Arms `action` for the end of the current item and returns that item's input.
+pub async fn arm(controller: &Controller, action: EndAction) -> Result<String> {
+    let status = rc::parse_status(&controller.forward(b"status\n").await?);
+    let Some(input) = status.input.clone().filter(|_| status.has_media()) else {
+        bail!("No media playing");
+    };
+
+    let state = &controller.stop_after_current;
+    *state.pending.lock().unwrap() = Some(Pending {
+        input: input.clone(),
+        action,
+    });
+    state.armed.notify_one();
+    info!(input = %input, action = ?action, "Armed stop after current item");
+    Ok(input)
+}
+
+/// Polls VLC's status while a stop is pending or a range loops; idle otherwise.
+pub async fn run(controller: Arc<Controller>) {
+    let stop = &controller.stop_after_current;
+    let looping = &controller.loop_range;
+    loop {
+        if stop.pending.lock().unwrap().is_none() && looping.range.lock().unwrap().is_none() {
+            tokio::select! {
+                _ = stop.armed.notified() => {}
+                _ = looping.armed.notified() => {}
+            }
+            continue;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let status = match controller.forward(b"status\n").await {
+            Ok(output) => rc::parse_status(&output),
+            Err(e) => {
+                debug!(error = %e, "Status poll failed");
+                continue;
+            }
+        };
+        check_stop_after_current(&controller, &status).await;
+        check_loop_range(&controller, &status).await;
+    }
+}
+
+async fn check_stop_after_current(controller: &Controller, status: &rc::Status) {
+    let action = {
+        let mut pending = controller.stop_after_current.pending.lock().unwrap();
+        let finished = match pending.as_ref() {
+            Some(armed) => status.input.as_deref() != Some(armed.input.as_str()) || !status.has_media(),
+            None => false,
+        };
+        if !finished {
+            return;
+        }
+        let armed = pending.take().unwrap();
+        // Already stopped at the end of the playlist: nothing left to do.
+        status.has_media().then_some(armed.action)
+    };
+
+    let Some(action) = action else {
+        info!("Current item finished, playback already stopped");
+        return;
+    };
+    match controller.forward(action.command()).await {
+        Ok(_) => {
+            controller.query_cache.clear();
+            info!(action = ?action, next_input = status.input.as_deref(), "Current item finished");
+        }
+        Err(e) => warn!(action = ?action, error = %e, "Failed to act after current item"),
+    }
+}
+
+/// Jumps back to the start of the range once VLC has moved past its end, or
+/// stopped because the range ends the playlist.
+async fn check_loop_range(controller: &Controller, status: &rc::Status) {
+    let Some(range) = *controller.loop_range.range.lock().unwrap() else {
+        return;
+    };
+    let items = match controller.forward(b"playlist\n").await {
+        Ok(output) => rc::parse_playlist(&output),
+        Err(e) => {
+            debug!(error = %e, "Playlist poll failed");
+            return;
+        }
+    };
+    let position = items.iter().position(|item| item.current).map(|index| index + 1);
+    let outside = match position {
+        Some(position) => position < range.start || position > range.end || (position == range.end && !status.has_media()),
+        // Older VLC versions do not mark the current item; all that can be
+        // seen then is playback stopping at the end of the playlist.
+        None => !status.has_media() && range.end == items.len(),
+    };
+    if !outside {
+        return;
+    }
+    // Cleared while the playlist was read.
+    if controller.loop_range.range.lock().unwrap().is_none() {
+        return;
+    }
+    match controller.forward(format!("goto {}\n", range.start).as_bytes()).await {
+        Ok(_) => {
+            controller.query_cache.clear();
+            debug!(start = range.start, end = range.end, left_at = position, "Looped back to the start of the range");
+        }
+        Err(e) => warn!(start = range.start, error = %e, "Failed to loop back to the start of the range"),
+    }
+}
@@ -0,0 +1,98 @@
+//! `--run-as-user`: start as root to bind privileged ports, then switch to an
+//! unprivileged user before serving. Switching away from root also clears
+//! every capability.
+
+use anyhow::{Result, bail};
+use tracing::info;
+
+/// Switches the process to `user` and `group` (default: the user's primary
+/// group), failing unless root can no longer be regained afterwards.
+///
+/// Must run after every listener is bound and every root-only file (TLS key,
+/// config) is read.
+#[cfg(unix)]
+pub fn drop_to(user: &str, group: Option<&str>) -> Result<()> {
+    use std::io::Error;
+
+    let (uid, primary_gid) = lookup_user(user)?;
+    let gid = match group {
+        Some(group) => lookup_group(group)?,
+        None => primary_gid,
+    };
+
+    // SAFETY: these calls only read or change the process credentials.
+    unsafe {
+        if libc::geteuid() != 0 {
+            if libc::getuid() == uid && libc::geteuid() == uid && libc::getegid() == gid {
+                info!(user = user, uid = uid, "Already running as --run-as-user");
+                return Ok(());
+            }
+            bail!("--run-as-user {} needs the controller to be started as root", user);
+        }
+
+        // Supplementary groups first: they can only be changed while still root.
+        // glibc and musl apply these to every thread of the process.
+        if libc::setgroups(1, &gid) != 0 {
+            bail!("dropping supplementary groups failed: {}", Error::last_os_error());
+        }
+        if libc::setgid(gid) != 0 {
+            bail!("switching to group {} failed: {}", gid, Error::last_os_error());
+        }
+        if libc::setuid(uid) != 0 {
+            bail!("switching to user {} failed: {}", user, Error::last_os_error());
+        }
+
+        if libc::getuid() != uid || libc::geteuid() != uid || libc::getgid() != gid || libc::getegid() != gid {
+            bail!("privileges were not fully dropped to {}, refusing to continue", user);
+        }
+        if uid != 0 && libc::setuid(0) == 0 {
+            bail!("root can still be regained after switching to {}, refusing to continue", user);
+        }
+    }
+    info!(user = user, uid = uid, gid = gid, "Dropped root privileges");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_to(_user: &str, _group: Option<&str>) -> Result<()> {
+    bail!("--run-as-user is not supported on this platform")
+}
+
+/// Uid and primary gid of `name`.
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<(libc::uid_t, libc::gid_t)> {
+    let c_name = std::ffi::CString::new(name)?;
+    let mut buf = vec![0; LOOKUP_BUF_LEN];
+    // SAFETY: `passwd` is plain data, filled in by getpwnam_r from `buf`.
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwnam_r(c_name.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if found.is_null() {
+        if rc != 0 {
+            bail!("looking up user {} failed: {}", name, std::io::Error::from_raw_os_error(rc));
+        }
+        bail!("--run-as-user: no user named {}", name);
+    }
+    Ok((passwd.pw_uid, passwd.pw_gid))
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Result<libc::gid_t> {
+    let c_name = std::ffi::CString::new(name)?;
+    let mut buf = vec![0; LOOKUP_BUF_LEN];
+    // SAFETY: `group` is plain data, filled in by getgrnam_r from `buf`.
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe { libc::getgrnam_r(c_name.as_ptr(), &mut group, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if found.is_null() {
+        if rc != 0 {
+            bail!("looking up group {} failed: {}", name, std::io::Error::from_raw_os_error(rc));
+        }
+        bail!("--run-as-group: no group named {}", name);
+    }
+    Ok(group.gr_gid)
+}
+
+/// Scratch space for the passwd/group string fields.
+#[cfg(unix)]
+const LOOKUP_BUF_LEN: usize = 16 * 1024;
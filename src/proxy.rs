@@ -0,0 +1,50 @@
+//! PROXY protocol v1 header parsing, for clients connecting through a load
+//! balancer such as HAProxy.
+//!
+//! Format: `PROXY TCP4|TCP6 <src ip> <dst ip> <src port> <dst port>\r\n`
+//! or `PROXY UNKNOWN ...\r\n`.
+
+use anyhow::{Result, bail};
+use std::net::{IpAddr, SocketAddr};
+
+/// Longest valid v1 header, including the CRLF.
+pub const MAX_HEADER_LEN: usize = 107;
+
+/// Parses a v1 header line, returning the original client address.
+///
+/// `None` means the proxy sent `UNKNOWN`, in which case the connection's
+/// own peer address should be used.
+pub fn parse_v1(line: &[u8]) -> Result<Option<SocketAddr>> {
+    let Some(line) = line.strip_suffix(b"\r\n") else {
+        bail!("PROXY header not terminated by CRLF");
+    };
+    let line = std::str::from_utf8(line)?;
+    let mut fields = line.split(' ');
+
+    if fields.next() != Some("PROXY") {
+        bail!("missing PROXY signature");
+    }
+    let family = fields.next().unwrap_or_default();
+    if family == "UNKNOWN" {
+        return Ok(None);
+    }
+
+    let fields: Vec<&str> = fields.collect();
+    let [src_ip, dst_ip, src_port, dst_port] = fields[..] else {
+        bail!("expected 4 address fields after {}", family);
+    };
+    let src_ip: IpAddr = src_ip.parse()?;
+    let dst_ip: IpAddr = dst_ip.parse()?;
+    let expect_v4 = match family {
+        "TCP4" => true,
+        "TCP6" => false,
+        other => bail!("unsupported protocol family {}", other),
+    };
+    if src_ip.is_ipv4() != expect_v4 || dst_ip.is_ipv4() != expect_v4 {
+        bail!("addresses do not match protocol family {}", family);
+    }
+    let src_port: u16 = src_port.parse()?;
+    dst_port.parse::<u16>()?;
+
+    Ok(Some(SocketAddr::new(src_ip, src_port)))
+}
@@ -0,0 +1,383 @@
+//! Parsers for the text VLC's RC interface prints in reply to commands.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Removes the trailing `>` prompt and surrounding whitespace from a response.
+pub fn strip_prompt(response: &str) -> &str {
+    response.trim().trim_end_matches('>').trim_end()
+}
+
+/// An entry of the playlist node in the output of the RC `playlist` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaylistItem {
+    pub name: String,
+    /// VLC marks the current item with `*`
+    pub current: bool,
+}
+
+/// Parses the RC `playlist` listing into the items of the playlist node.
+///
+/// VLC prints a tree such as:
+///
+/// ```text
+/// +----[ Playlist - playlist ]
+/// | 1 - Playlist
+/// |   4 - intro.mp4 (00:01:10) [played 2 times]
+/// | 2 - Media Library
+/// +----[ End of playlist ]
+/// ```
+///
+/// The first top-level node is the playlist (its name is localised, so it is
+/// matched by position); everything indented below it is an item.
+pub fn parse_playlist(output: &str) -> Vec<PlaylistItem> {
+    let mut items = Vec::new();
+    let mut root_indent = None;
+
+    for line in output.lines() {
+        let Some(entry) = line.strip_prefix('|') else {
+            continue;
+        };
+        let indent = entry.len() - entry.trim_start().len();
+        let Some(name) = entry_name(entry.trim()) else {
+            continue;
+        };
+
+        match root_indent {
+            None => root_indent = Some(indent),
+            Some(root) if indent > root => items.push(PlaylistItem {
+                name: strip_item_suffixes(name).to_string(),
+                current: entry.trim().starts_with('*'),
+            }),
+            Some(_) => break,
+        }
+    }
+    items
+}
+
+/// Splits `[*]<id> - <name>` and returns the name.
+fn entry_name(entry: &str) -> Option<&str> {
+    let entry = entry.trim_start_matches('*');
+    let (id, name) = entry.split_once(" - ")?;
+    id.trim().parse::<u32>().ok()?;
+    Some(name.trim())
+}
+
+/// Drops the ` (hh:mm:ss)` duration and ` [played N times]` annotations.
+fn strip_item_suffixes(name: &str) -> &str {
+    let mut name = name.trim_end();
+    if name.ends_with(']')
+        && let Some(start) = name.rfind(" [")
+    {
+        name = name[..start].trim_end();
+    }
+    if name.ends_with(')')
+        && let Some(start) = name.rfind(" (")
+    {
+        name = name[..start].trim_end();
+    }
+    name
+}
+
+/// Fields of the RC `status` output, e.g.:
+///
+/// ```text
+/// ( new input: file:///media/intro.mp4 )
+/// ( audio volume: 256 )
+/// ( state playing )
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Status {
+    /// URI of the current input, absent when nothing is loaded
+    pub input: Option<String>,
+    /// Playback state such as `playing`, `paused` or `stopped`
+    pub state: Option<String>,
+}
+
+impl Status {
+    /// True if an input is loaded and not stopped.
+    pub fn has_media(&self) -> bool {
+        self.input.is_some() && self.state.as_deref() != Some("stopped")
+    }
+
+    /// True if the state says playing, however the VLC version spells it
+    /// (`playing`, `play`, `PLAYING_S`, ...).
+    pub fn is_playing(&self) -> bool {
+        self.state.as_deref().is_some_and(|state| state.trim().to_ascii_lowercase().starts_with("play"))
+    }
+}
+
+/// Parses the `( key: value )` lines printed by the RC `status` command.
+pub fn parse_status(output: &str) -> Status {
+    let mut status = Status::default();
+    for line in output.lines() {
+        let Some(field) = line.trim().strip_prefix('(').and_then(|l| l.strip_suffix(')')) else {
+            continue;
+        };
+        let field = field.trim();
+        if let Some(input) = field.strip_prefix("new input:") {
+            status.input = Some(input.trim().to_string());
+        } else if let Some(state) = field.strip_prefix("state ") {
+            status.state = Some(state.trim().to_string());
+        }
+    }
+    status
+}
+
+/// Parses the whole-second value printed by `get_time` or `get_length`.
+///
+/// VLC prints an empty line when there is no current input, which yields `None`.
+pub fn parse_seconds(output: &str) -> Option<u64> {
+    output.trim().parse().ok()
+}
+
+/// Parses the volume printed by a bare RC `volume`, either `256` or
+/// `( audio volume: 256 )` depending on the VLC version.
+pub fn parse_volume(output: &str) -> Option<u32> {
+    let output = output.trim();
+    let value = output
+        .strip_prefix('(')
+        .and_then(|field| field.strip_suffix(')'))
+        .and_then(|field| field.trim().strip_prefix("audio volume:"))
+        .unwrap_or(output);
+    value.trim().parse().ok()
+}
+
+/// Parses the playback rate printed by a bare RC `rate`, e.g. `1.50`.
+pub fn parse_rate(output: &str) -> Option<f64> {
+    output.trim().parse().ok().filter(|rate: &f64| rate.is_finite())
+}
+
+/// Metadata and stream details of the current input, from the RC `info` command.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct MediaInfo {
+    /// `title`, `artist`, `filename`, ... as VLC names them
+    pub meta: BTreeMap<String, String>,
+    /// One map per elementary stream (`Type`, `Codec`, `Resolution`, ...)
+    pub streams: Vec<BTreeMap<String, String>>,
+}
+
+/// Parses the sections of the RC `info` output, e.g.:
+///
+/// ```text
+/// +----[ Meta data ]
+/// |
+/// | title: Big Buck Bunny
+/// | filename: bbb.mp4
+/// |
+/// +----[ Stream 0 ]
+/// |
+/// | Codec: H264 - MPEG-4 AVC (part 10) (h264)
+/// | Type: Video
+/// |
+/// +----[ end of stream info ]
+/// ```
+///
+/// Section titles are localised, so streams are recognised by the number that
+/// ends their title and any other section is taken as metadata. Without a
+/// current input VLC prints no sections, which yields an empty `MediaInfo`.
+pub fn parse_info(output: &str) -> MediaInfo {
+    enum Section {
+        None,
+        Meta,
+        Stream,
+    }
+
+    let mut info = MediaInfo::default();
+    let mut section = Section::None;
+    for line in output.lines() {
+        let line = line.trim_end();
+        if let Some(title) = line.strip_prefix("+----[").and_then(|rest| rest.strip_suffix(']')) {
+            let title = title.trim();
+            section = if title.eq_ignore_ascii_case("end of stream info") {
+                Section::None
+            } else if title.rsplit(' ').next().is_some_and(|last| last.parse::<u32>().is_ok()) {
+                info.streams.push(BTreeMap::new());
+                Section::Stream
+            } else {
+                Section::Meta
+            };
+            continue;
+        }
+
+        let Some((key, value)) = line.strip_prefix('|').and_then(|entry| entry.trim().split_once(": ")) else {
+            continue;
+        };
+        let (key, value) = (key.trim().to_string(), value.trim().to_string());
+        match section {
+            Section::Meta => {
+                info.meta.insert(key, value);
+            }
+            Section::Stream => {
+                if let Some(stream) = info.streams.last_mut() {
+                    stream.insert(key, value);
+                }
+            }
+            Section::None => {}
+        }
+    }
+    info
+}
+
+/// An entry of the `atrack`/`strack` listings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Track {
+    /// Index to pass to `atrack`/`strack`; `-1` disables the track type
+    pub id: i64,
+    pub name: String,
+    pub selected: bool,
+}
+
+/// Parses the listing printed by a bare `atrack` or `strack`:
+///
+/// ```text
+/// +----[ audio-es ]
+/// | -1 - Disable
+/// | 1 - Track 1 - [English]
+/// | 2 - Track 2 - [Français] *
+/// +----[ end of audio-es ]
+/// ```
+///
+/// The selected track ends in ` *`. Without a current input VLC lists no tracks.
+pub fn parse_tracks(output: &str) -> Vec<Track> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let entry = line.trim_end().strip_prefix('|')?.trim();
+            let (entry, selected) = match entry.strip_suffix(" *") {
+                Some(entry) => (entry, true),
+                None => (entry, false),
+            };
+            let (id, name) = entry.split_once(" - ")?;
+            Some(Track {
+                id: id.trim().parse().ok()?,
+                name: name.trim().to_string(),
+                selected,
+            })
+        })
+        .collect()
+}
+
+/// Parses the value marked current in the listing printed by a bare `vratio`
+/// or `vcrop`:
+///
+/// ```text
+/// +----[ aspect-ratio ]
+/// |  - Default
+/// | 16:9 - 16:9 *
+/// | 4:3 - 4:3
+/// +----[ end of aspect-ratio ]
+/// ```
+///
+/// The default has an empty value and is reported as `default`. Without a
+/// video output VLC lists nothing.
+pub fn parse_video_setting(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let entry = line.trim_end().strip_prefix('|')?.strip_suffix(" *")?;
+        let (value, _) = entry.split_once(" - ")?;
+        Some(match value.trim() {
+            "" => "default".to_string(),
+            value => value.to_string(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `info` as printed by VLC 3.0.18 for a local MP4, prompt included.
+    const INFO_OUTPUT: &str = "+----[ Meta data ]\r\n\
+        |\r\n\
+        | title: Big Buck Bunny\r\n\
+        | artist: Blender Foundation\r\n\
+        | filename: bbb_sunflower_1080p_30fps_normal.mp4\r\n\
+        | url: http://example.com/a:b\r\n\
+        |\r\n\
+        +----[ Stream 0 ]\r\n\
+        |\r\n\
+        | Codec: H264 - MPEG-4 AVC (part 10) (avc1)\r\n\
+        | Language: English\r\n\
+        | Type: Video\r\n\
+        | Video resolution: 1920x1080\r\n\
+        |\r\n\
+        +----[ Stream 1 ]\r\n\
+        |\r\n\
+        | Codec: MPEG Audio layer 1/2/3 (mpga)\r\n\
+        | Type: Audio\r\n\
+        | Channels: Stereo\r\n\
+        |\r\n\
+        +----[ end of stream info ]\r\n\
+        > ";
+
+    #[test]
+    fn parses_meta_and_streams() {
+        let info = parse_info(INFO_OUTPUT);
+        assert_eq!(info.meta["title"], "Big Buck Bunny");
+        assert_eq!(info.meta["artist"], "Blender Foundation");
+        assert_eq!(info.meta["filename"], "bbb_sunflower_1080p_30fps_normal.mp4");
+        assert_eq!(info.streams.len(), 2);
+        assert_eq!(info.streams[0]["Type"], "Video");
+        assert_eq!(info.streams[0]["Video resolution"], "1920x1080");
+        assert_eq!(info.streams[1]["Codec"], "MPEG Audio layer 1/2/3 (mpga)");
+    }
+
+    #[test]
+    fn values_keep_their_colons() {
+        let info = parse_info(INFO_OUTPUT);
+        assert_eq!(info.meta["url"], "http://example.com/a:b");
+    }
+
+    #[test]
+    fn localised_section_titles() {
+        let output = "+----[ Méta-données ]\n| title: Sintel\n+----[ Flux 0 ]\n| Type: Vidéo\n+----[ end of stream info ]\n";
+        let info = parse_info(output);
+        assert_eq!(info.meta["title"], "Sintel");
+        assert_eq!(info.streams[0]["Type"], "Vidéo");
+    }
+
+    #[test]
+    fn parses_tracks_and_selection() {
+        let output = "+----[ audio-es ]\r\n| -1 - Disable\r\n| 1 - Track 1 - [English]\r\n\
+            | 2 - Track 2 - [Français] *\r\n+----[ end of audio-es ]\r\n";
+        let tracks = parse_tracks(output);
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].id, -1);
+        assert_eq!(tracks[2].name, "Track 2 - [Français]");
+        assert!(tracks[2].selected && !tracks[1].selected);
+        assert!(parse_tracks("+----[ spu-es ]\r\n+----[ end of spu-es ]\r\n").is_empty());
+    }
+
+    #[test]
+    fn parses_the_current_video_setting() {
+        let output = "+----[ aspect-ratio ]\r\n|  - Default\r\n| 16:9 - 16:9 *\r\n| 4:3 - 4:3\r\n+----[ end of aspect-ratio ]\r\n";
+        assert_eq!(parse_video_setting(output).as_deref(), Some("16:9"));
+        let output = "+----[ crop ]\r\n|  - Default *\r\n| 16:10 - 16:10\r\n+----[ end of crop ]\r\n";
+        assert_eq!(parse_video_setting(output).as_deref(), Some("default"));
+        assert_eq!(parse_video_setting(""), None);
+    }
+
+    #[test]
+    fn parses_recorded_sessions() {
+        for (name, text) in crate::testing::FIXTURES {
+            let transcript = crate::testing::Transcript::parse(text);
+            let reply = |command| transcript.reply(command).unwrap_or_else(|| panic!("{}: no {}", name, command));
+
+            let status = parse_status(reply("status"));
+            assert!(status.has_media() && status.is_playing(), "{}", name);
+            assert!(!parse_playlist(reply("playlist")).is_empty(), "{}", name);
+            let info = parse_info(reply("info"));
+            assert!(info.meta.contains_key("title"), "{}", name);
+            assert!(info.streams.iter().any(|stream| stream.get("Type").is_some_and(|t| t == "Video")), "{}", name);
+            assert!(parse_seconds(reply("get_time")) < parse_seconds(reply("get_length")), "{}", name);
+            assert!(parse_volume(reply("volume")).is_some(), "{}", name);
+            assert!(parse_tracks(reply("atrack")).iter().any(|track| track.selected), "{}", name);
+        }
+    }
+
+    #[test]
+    fn no_input_is_empty() {
+        assert_eq!(parse_info(""), MediaInfo::default());
+        assert_eq!(parse_info("> "), MediaInfo::default());
+    }
+}
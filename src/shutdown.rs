@@ -0,0 +1,196 @@
+//! Graceful shutdown: refuse new commands and let in-flight ones finish.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// Tracks commands in flight so shutdown can wait for them.
+pub struct Shutdown {
+    triggered: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+    quit: Notify,
+    /// When a command last began or finished
+    last_activity: Mutex<Instant>,
+    /// Verb and client of each command in flight, by registration ID
+    running: Mutex<HashMap<u64, (String, SocketAddr)>>,
+    next_id: AtomicU64,
+}
+
+/// Marks one command as in flight until dropped.
+pub struct InFlight<'a> {
+    shutdown: &'a Shutdown,
+    id: u64,
+}
+
+/// Like `InFlight`, but can be moved into a spawned task.
+pub struct OwnedInFlight {
+    shutdown: Arc<Shutdown>,
+    id: u64,
+}
+
+/// Outcome of `Shutdown::drain`.
+pub struct DrainReport {
+    /// Commands that were in flight at shutdown and finished in time
+    pub processed: usize,
+    /// Commands still running when the timeout elapsed
+    pub dropped: usize,
+    /// Verb and client of each dropped command
+    pub abandoned: Vec<(String, SocketAddr)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            triggered: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            idle: Notify::new(),
+            quit: Notify::new(),
+            last_activity: Mutex::new(Instant::now()),
+            running: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Resolves once no command has been running for `window` (`--idle-exit-secs`).
+    pub async fn idle_for(&self, window: Duration) {
+        loop {
+            let last_activity = *self.last_activity.lock().unwrap();
+            if self.in_flight.load(Ordering::SeqCst) > 0 {
+                tokio::time::sleep(window).await;
+            } else if last_activity.elapsed() >= window {
+                return;
+            } else {
+                tokio::time::sleep_until(last_activity + window).await;
+            }
+        }
+    }
+
+    /// Asks the controller to exit gracefully, as on SIGTERM (`pi_quit`).
+    pub fn request_quit(&self) {
+        self.quit.notify_one();
+    }
+
+    /// Resolves once `request_quit` has been called.
+    pub async fn quit_requested(&self) {
+        self.quit.notified().await;
+    }
+
+    /// Registers a new command from `client`, or returns `None` once shutdown has begun.
+    pub fn begin(&self, command: &str, client: SocketAddr) -> Option<InFlight<'_>> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let verb = command.split_whitespace().next().unwrap_or_default().to_string();
+        self.running.lock().unwrap().insert(id, (verb, client));
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        if self.triggered.load(Ordering::SeqCst) {
+            self.finish(id);
+            return None;
+        }
+        self.touch();
+        Some(InFlight { shutdown: self, id })
+    }
+
+    /// Like `begin`, for commands that finish in a background task.
+    pub fn begin_owned(self: &Arc<Self>, command: &str, client: SocketAddr) -> Option<OwnedInFlight> {
+        let in_flight = self.begin(command, client)?;
+        let id = in_flight.id;
+        std::mem::forget(in_flight);
+        Some(OwnedInFlight { shutdown: self.clone(), id })
+    }
+
+    /// Stops accepting commands and waits up to `timeout` for in-flight ones.
+    pub async fn drain(&self, timeout: Duration) -> DrainReport {
+        self.triggered.store(true, Ordering::SeqCst);
+        let pending = self.in_flight.load(Ordering::SeqCst);
+
+        let _ = tokio::time::timeout(timeout, async {
+            loop {
+                let idle = self.idle.notified();
+                if self.in_flight.load(Ordering::SeqCst) == 0 {
+                    break;
+                }
+                idle.await;
+            }
+        })
+        .await;
+
+        let dropped = self.in_flight.load(Ordering::SeqCst);
+        DrainReport {
+            processed: pending.saturating_sub(dropped),
+            dropped,
+            abandoned: self.running.lock().unwrap().values().cloned().collect(),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    fn finish(&self, id: u64) {
+        self.running.lock().unwrap().remove(&id);
+        self.touch();
+        if self.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.idle.notify_waiters();
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.shutdown.finish(self.id);
+    }
+}
+
+impl Drop for OwnedInFlight {
+    fn drop(&mut self) {
+        self.shutdown.finish(self.id);
+    }
+}
+
+/// Resolves on SIGINT or, on Unix, SIGTERM.
+pub async fn signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+
+    #[cfg(unix)]
+    {
+        let mut term = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(term) => term,
+            Err(_) => {
+                let _ = ctrl_c.await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = term.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = ctrl_c.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_names_the_commands_it_abandons() {
+        let shutdown = Shutdown::new();
+        let client: SocketAddr = "192.0.2.7:5000".parse().unwrap();
+        let finished = shutdown.begin("play", client);
+        let _running = shutdown.begin("seek 10", client);
+        drop(finished);
+
+        let report = shutdown.drain(Duration::from_millis(10)).await;
+        assert_eq!((report.processed, report.dropped), (0, 1));
+        assert_eq!(report.abandoned, [("seek".to_string(), client)]);
+        assert!(shutdown.begin("play", client).is_none());
+    }
+}
@@ -0,0 +1,54 @@
+//! JSON-line sinks for `--completion-sink` and `--connection-events`, so
+//! test harnesses and monitoring can follow the controller without parsing
+//! its logs.
+//!
+//! If the path is a Unix socket each line is sent over a new stream
+//! connection to it; otherwise it is appended to the file. Lines go out in
+//! the order they were emitted.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+pub struct JsonLineSink {
+    lines: mpsc::UnboundedSender<String>,
+}
+
+impl JsonLineSink {
+    /// Starts the task writing to `path`. Needs a Tokio runtime.
+    pub fn new(path: PathBuf) -> Self {
+        let (lines, mut pending) = mpsc::unbounded_channel::<String>();
+        tokio::spawn(async move {
+            while let Some(line) = pending.recv().await {
+                if let Err(e) = write(&path, line.as_bytes()).await {
+                    warn!(path = %path.display(), error = %e, "Could not write to JSON line sink");
+                }
+            }
+        });
+        Self { lines }
+    }
+
+    pub fn emit<T: Serialize>(&self, record: &T) {
+        match serde_json::to_string(record) {
+            Ok(line) => {
+                let _ = self.lines.send(line + "\n");
+            }
+            Err(e) => debug!(error = %e, "Could not encode JSON line"),
+        }
+    }
+}
+
+async fn write(path: &Path, line: &[u8]) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if tokio::fs::metadata(path).await.is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            let mut stream = tokio::net::UnixStream::connect(path).await?;
+            return stream.write_all(line).await;
+        }
+    }
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(line).await
+}
@@ -0,0 +1,61 @@
+//! The `snapshot` command: VLC's RC `snapshot`, plus the path of the image
+//! when `--snapshot-dir` names the directory VLC saves to (its `--snapshot-path`).
+
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+
+use crate::Controller;
+
+/// How long to wait for VLC to write the image.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// The reply without `--snapshot-dir`, when only VLC knows where the image went.
+pub const UNKNOWN_PATH: &str = "snapshot requested; its path is only reported with --snapshot-dir";
+
+/// Fails unless `dir` is a directory we can create files in, as VLC must.
+pub fn check_dir(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        bail!("--snapshot-dir {} is not a directory", dir.display());
+    }
+    let probe = dir.join(format!(".vlc-control-probe-{}", std::process::id()));
+    std::fs::write(&probe, b"").with_context(|| format!("--snapshot-dir {} is not writable", dir.display()))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Takes a snapshot, returning the new file's path if the directory is known
+/// and otherwise saying where to find it.
+pub async fn take(controller: &Controller, dir: Option<&Path>) -> Result<String> {
+    let Some(dir) = dir else {
+        controller.forward(b"snapshot\n").await?;
+        return Ok(UNKNOWN_PATH.to_string());
+    };
+    let before = list(dir)?;
+    controller.forward(b"snapshot\n").await?;
+
+    let deadline = tokio::time::Instant::now() + WRITE_TIMEOUT;
+    loop {
+        let mut new: Vec<PathBuf> = list(dir)?.difference(&before).map(|name| dir.join(name)).collect();
+        if let Some(path) = new.pop() {
+            info!(path = %path.display(), "Snapshot taken");
+            return Ok(path.display().to_string());
+        }
+        if tokio::time::Instant::now() >= deadline {
+            bail!(
+                "VLC wrote no snapshot to {} within {}s; is video playing and VLC's --snapshot-path set to it?",
+                dir.display(),
+                WRITE_TIMEOUT.as_secs()
+            );
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+fn list(dir: &Path) -> Result<HashSet<OsString>> {
+    let entries = std::fs::read_dir(dir).with_context(|| format!("reading snapshot dir {}", dir.display()))?;
+    Ok(entries.filter_map(|entry| entry.ok().map(|entry| entry.file_name())).collect())
+}
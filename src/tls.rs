@@ -0,0 +1,82 @@
+//! TLS for the TCP server (`--tls-cert`, `--tls-key`).
+//!
+//! With `--tls-client-ca` it is mutual TLS: clients must present a
+//! certificate signed by that CA or the handshake fails. The certificate's
+//! common name and DNS/email SANs identify the client in logs and can select
+//! its allowlist profile (`cert_name` in `[[clients]]`).
+
+use anyhow::{Context, Result};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig, crypto};
+use x509_parser::extensions::GeneralName;
+
+tokio::task_local! {
+    /// Names from the client certificate of the connection being served.
+    static PEER_NAMES: Vec<String>;
+}
+
+pub fn acceptor(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<TlsAcceptor> {
+    let certs = read_certs(cert)?;
+    let key = PrivateKeyDer::from_pem_file(key).with_context(|| format!("reading TLS key {}", key.display()))?;
+
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca)? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("reading certificates from {}", path.display()))?;
+    anyhow::ensure!(!certs.is_empty(), "no certificates in {}", path.display());
+    Ok(certs)
+}
+
+/// The common name, then the DNS and email SANs of a client certificate.
+pub fn cert_names(cert: &CertificateDer) -> Vec<String> {
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(cert) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = cert
+        .subject()
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(str::to_string)
+        .collect();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            if let GeneralName::DNSName(name) | GeneralName::RFC822Name(name) = name {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Runs `future` with `names` as the certificate names of the current client.
+pub async fn with_peer_names<F: Future>(names: Vec<String>, future: F) -> F::Output {
+    PEER_NAMES.scope(names, future).await
+}
+
+/// Certificate names of the client whose command is being processed; empty
+/// without mutual TLS.
+pub fn peer_names() -> Vec<String> {
+    PEER_NAMES.try_with(Clone::clone).unwrap_or_default()
+}
@@ -0,0 +1,110 @@
+//! Per-command rewriting of VLC's replies, for clients that expect another
+//! shape. Configured in the `[response_transforms]` section of the `--config`
+//! file, keyed by command verb:
+//!
+//! ```toml
+//! [response_transforms.status]
+//! extract = "state"
+//! json_key = "state"
+//!
+//! [response_transforms.get_title]
+//! strip_prefix = "Title: "
+//! ```
+//!
+//! The steps of a transformation run in the order `extract`, `strip_prefix`,
+//! `strip_suffix`, `json_key`; any may be left out. Only replies VLC produced
+//! are rewritten, not those of the controller's own commands.
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// The transformations of every configured verb.
+#[derive(Deserialize, Default)]
+#[serde(transparent)]
+pub struct ResponseTransforms(HashMap<String, Transform>);
+
+/// What to do with the reply to one verb.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transform {
+    /// Keep only the value of this field, from a line such as `( state playing )`,
+    /// `( audio volume: 256 )` or `| Title: intro`
+    extract: Option<String>,
+    strip_prefix: Option<String>,
+    strip_suffix: Option<String>,
+    /// Reply with `{"<json_key>": "<response>"}` instead
+    json_key: Option<String>,
+}
+
+impl ResponseTransforms {
+    /// Rewrites the reply to `verb`, unchanged if no transformation is configured.
+    pub fn apply(&self, verb: &str, response: String) -> Result<String> {
+        match self.0.get(verb) {
+            Some(transform) => transform.apply(verb, response),
+            None => Ok(response),
+        }
+    }
+}
+
+impl Transform {
+    fn apply(&self, verb: &str, mut response: String) -> Result<String> {
+        if let Some(field) = &self.extract {
+            let Some(value) = extract(&response, field) else {
+                bail!("Response to {} has no field '{}'", verb, field);
+            };
+            response = value.to_string();
+        }
+        if let Some(prefix) = &self.strip_prefix
+            && let Some(rest) = response.strip_prefix(prefix.as_str())
+        {
+            response = rest.to_string();
+        }
+        if let Some(suffix) = &self.strip_suffix
+            && let Some(rest) = response.strip_suffix(suffix.as_str())
+        {
+            response = rest.to_string();
+        }
+        if let Some(key) = &self.json_key {
+            response = serde_json::json!({ key: response }).to_string();
+        }
+        Ok(response)
+    }
+}
+
+/// Value of the first line naming `field`, after the RC decorations around it.
+fn extract<'a>(response: &'a str, field: &str) -> Option<&'a str> {
+    response.lines().find_map(|line| {
+        let line = line.trim();
+        let line = line.strip_prefix('(').and_then(|l| l.strip_suffix(')')).unwrap_or(line);
+        let line = line.strip_prefix('|').unwrap_or(line).trim();
+        let rest = line.strip_prefix(field)?;
+        let value = rest.strip_prefix(':').or_else(|| rest.strip_prefix(' '))?;
+        Some(value.trim())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transforms(config: &str) -> ResponseTransforms {
+        toml::from_str(config).unwrap()
+    }
+
+    #[test]
+    fn applies_the_steps_in_order() {
+        let transforms = transforms("[status]\nextract = \"state\"\nstrip_suffix = \"ing\"\njson_key = \"state\"\n");
+        let status = "( new input: file:///intro.mp4 )\n( audio volume: 256 )\n( state playing )";
+        assert_eq!(transforms.apply("status", status.to_string()).unwrap(), r#"{"state":"play"}"#);
+        assert_eq!(transforms.apply("volume", "256".to_string()).unwrap(), "256");
+        assert!(transforms.apply("status", "( audio volume: 256 )".to_string()).is_err());
+    }
+
+    #[test]
+    fn extracts_colon_fields() {
+        assert_eq!(extract("( audio volume: 256 )", "audio volume"), Some("256"));
+        assert_eq!(extract("| Title: intro", "Title"), Some("intro"));
+        assert_eq!(extract("| Titles: 2", "Title"), None);
+    }
+}
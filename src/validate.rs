@@ -0,0 +1,135 @@
+//! `--test-config`: check the options and the `--config` file without
+//! starting anything, so a config can be vetted before it is rolled out.
+//!
+//! Every check runs, so one pass reports all problems. Listening addresses
+//! are parsed but not bound, and VLC is not contacted.
+
+use anyhow::Result;
+use std::net::ToSocketAddrs;
+
+use crate::access::AccessPolicy;
+use crate::{Args, commands, config, fifo, listen, playlist_file, snapshot, tls_acceptor};
+
+/// Problems found in the configuration, each with what it concerns; empty if it is usable.
+pub fn check(args: &Args) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut note = |what: &str, result: Result<()>| {
+        if let Err(e) = result {
+            problems.push(format!("{}: {:#}", what, e));
+        }
+    };
+
+    for (option, addr) in [("--tcp-address", Some(&args.tcp_address)), ("--udp-address", Some(&args.udp_address))]
+        .into_iter()
+        .chain([("--metrics-address", args.metrics_address.as_ref())])
+    {
+        if let Some(addr) = addr {
+            note(option, listen_address(addr));
+        }
+    }
+    note("--vlc-address", vlc_address(&args.vlc_address));
+    if let Some(addr) = &args.vlc_address_fallback {
+        note("--vlc-address-fallback", vlc_address(addr));
+    }
+    if args.reuse_port && !listen::REUSE_PORT_SUPPORTED {
+        note("--reuse-port", Err(anyhow::anyhow!("not supported on this platform")));
+    }
+    note("TLS", tls_acceptor(args).map(drop));
+    if let Some(dir) = &args.snapshot_dir {
+        note("--snapshot-dir", snapshot::check_dir(dir));
+    }
+    if let Some(dir) = &args.playlist_dir {
+        note("--playlist-dir", playlist_file::check_dir(dir));
+    }
+    if let Some(path) = &args.fifo {
+        note("--fifo", fifo::check(path));
+    }
+
+    if let Some(path) = &args.config {
+        match config::load_access(path).and_then(AccessPolicy::from_config) {
+            Ok(Some(policy)) => {
+                for problem in unknown_allowlist_commands(&policy, &args.mgmt_prefix) {
+                    problems.push(problem);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => problems.push(format!("allowlist: {:#}", e)),
+        }
+        if let Err(e) = config::load_response_timeouts(path) {
+            problems.push(format!("response timeouts: {:#}", e));
+        }
+        if let Err(e) = config::load_response_transforms(path) {
+            problems.push(format!("response transforms: {:#}", e));
+        }
+        if let Err(e) = config::load_aliases(path) {
+            problems.push(format!("aliases: {:#}", e));
+        }
+    }
+    problems
+}
+
+/// A `host:port` to listen on; names are resolved, as binding would.
+fn listen_address(addr: &str) -> Result<()> {
+    let mut resolved = addr.to_socket_addrs().map_err(|e| anyhow::anyhow!("invalid address '{}': {}", addr, e))?;
+    if resolved.next().is_none() {
+        anyhow::bail!("'{}' resolves to no address", addr);
+    }
+    Ok(())
+}
+
+/// A `host:port` VLC listens on. The host is not resolved, since it need not
+/// be reachable from where the config is checked.
+fn vlc_address(addr: &str) -> Result<()> {
+    let valid = addr
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port > 0));
+    if !valid {
+        anyhow::bail!("invalid address '{}' (expected host:port)", addr);
+    }
+    Ok(())
+}
+
+/// Allowlist patterns that match no command the controller knows.
+fn unknown_allowlist_commands(policy: &AccessPolicy, mgmt_prefix: &str) -> Vec<String> {
+    let known: Vec<String> = commands::list(mgmt_prefix).into_iter().map(|command| command.name).collect();
+    let mut problems = Vec::new();
+    for (profile, patterns) in policy.profiles() {
+        for pattern in patterns {
+            let matches = match pattern.strip_suffix('*') {
+                Some(prefix) => known.iter().any(|name| name.starts_with(prefix)),
+                None => known.contains(pattern),
+            };
+            if !matches {
+                problems.push(format!("profile '{}': '{}' matches no known command", profile, pattern));
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn reports_every_problem() {
+        let path = std::env::temp_dir().join(format!("vlc-control-test-config-{}.toml", std::process::id()));
+        let config = "[profiles.kiosk]\ncommands = [\"play\", \"plya\", \"pi_*\", \"xyz*\"]\n\
+            [[clients]]\ncidr = \"192.168.1.0/33\"\nprofile = \"kiosk\"\n";
+        std::fs::write(&path, config).unwrap();
+        let args = Args::parse_from(["vlc-control", "--vlc-address", "vlc", "--tcp-address", "0.0.0.0:55550"]);
+        assert_eq!(check(&args), ["--vlc-address: invalid address 'vlc' (expected host:port)"]);
+
+        let args = Args::parse_from(["vlc-control", "--config", path.to_str().unwrap(), "--tls-key", "key.pem"]);
+        let problems = check(&args);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("TLS: --tls-cert and --tls-key must be given together"));
+        assert!(problems[1].starts_with("allowlist: invalid cidr '192.168.1.0/33'"));
+
+        std::fs::write(&path, config.replace("/33", "/24")).unwrap();
+        let problems = check(&args);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(problems[1..], ["profile 'kiosk': 'plya' matches no known command", "profile 'kiosk': 'xyz*' matches no known command"]);
+    }
+}
@@ -0,0 +1,743 @@
+//! Client side of VLC's RC (remote control) interface.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpSocket, TcpStream};
+use tracing::{debug, error, info, warn};
+
+use crate::rc;
+use failure_log::{FailureLog, Verdict};
+
+mod error;
+mod failure_log;
+mod http;
+mod retry_budget;
+mod srv;
+mod stdin;
+
+pub use error::{ErrorClass, ForwardError};
+pub use retry_budget::RetryBudget;
+pub use srv::SrvResolver;
+pub use stdin::ReconnectReason;
+
+/// How long to wait for a response when VLC may never print a trailing prompt.
+const NO_BANNER_RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// RC commands that end the session (`logout`) or VLC itself (`quit`, `shutdown`).
+const CLOSING_COMMANDS: &[&str] = &["logout", "quit", "shutdown"];
+
+/// Future returned by `VlcTransport` methods.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// How the controller reaches VLC. `VlcClient` is the real implementation;
+/// unit tests substitute an in-process one.
+pub trait VlcTransport: Send + Sync {
+    /// Address reported by `pi_status`
+    fn addr(&self) -> &str;
+
+    /// Sends one raw command and returns VLC's response.
+    fn forward_with_retry<'a>(&'a self, command: &'a [u8]) -> BoxFuture<'a, Result<String>>;
+
+    /// Connection state reported by `pi_diag`
+    fn diagnostics(&self) -> VlcDiagnostics {
+        VlcDiagnostics::default()
+    }
+
+    /// Health of each VLC instance commands may go to, reported by `pi_backends`
+    fn backends(&self) -> Vec<BackendStatus> {
+        Vec::new()
+    }
+
+    /// Closes a persistent connection to VLC and opens it again for
+    /// `pi_vlc_reconnect`, describing the new one; `None` when each command
+    /// connects afresh and there is nothing to reconnect.
+    fn reconnect(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Churn of the persistent connection to VLC, reported by `pi_vlc_stats` and `/metrics`
+    fn connection_stats(&self) -> ConnectionStats {
+        ConnectionStats::default()
+    }
+}
+
+/// The client's view of its connections to VLC, for `pi_diag`.
+#[derive(Serialize, Default)]
+pub struct VlcDiagnostics {
+    /// Address the last `--vlc-srv` lookup gave
+    pub srv_target: Option<String>,
+    /// Most recent connection attempt to the primary address
+    pub last_connect: Option<ConnectReport>,
+    pub fallback_address: Option<String>,
+    /// Most recent connection attempt to the fallback address
+    pub fallback_last_connect: Option<ConnectReport>,
+    /// Retries left in `--vlc-retry-budget`; `None` when retries are not budgeted
+    pub retry_budget_remaining: Option<u32>,
+    pub retry_budget_capacity: Option<u32>,
+}
+
+/// Outcome of one connection attempt.
+#[derive(Serialize, Clone)]
+pub struct ConnectReport {
+    pub ok: bool,
+    pub error: Option<String>,
+    pub latency_ms: u64,
+    pub secs_ago: u64,
+}
+
+/// Health of one VLC backend, for `pi_backends`.
+#[derive(Serialize)]
+pub struct BackendStatus {
+    pub address: String,
+    /// `primary`, or `fallback` for `--vlc-address-fallback`
+    pub role: &'static str,
+    /// Whether the last command sent to it got through; `None` before the first
+    pub reachable: Option<bool>,
+    /// Commands it answered, and commands that failed after their retries
+    pub successes: u64,
+    pub failures: u64,
+    /// Seconds since it last answered a command
+    pub last_contact_secs_ago: Option<u64>,
+    /// Most recent connection attempt
+    pub last_connect: Option<ConnectReport>,
+}
+
+/// Reconnects of the persistent connection to VLC, for `pi_vlc_stats`.
+#[derive(Serialize, Default)]
+pub struct ConnectionStats {
+    /// False when each command opens its own connection and there is nothing to track
+    pub persistent: bool,
+    /// Times a new connection replaced one that had ended
+    pub reconnects: u64,
+    /// When the last reconnect happened, in milliseconds since the Unix epoch
+    pub last_reconnect_timestamp_ms: Option<u128>,
+    pub last_reconnect_reason: Option<ReconnectReason>,
+    /// How long the current connection has been open; `None` while there is none
+    pub connection_age_secs: Option<u64>,
+}
+
+/// Outcomes of the commands sent to one backend.
+#[derive(Default)]
+struct BackendStats {
+    successes: u64,
+    failures: u64,
+    last_success: Option<Instant>,
+    last_ok: Option<bool>,
+}
+
+struct LastConnect {
+    error: Option<String>,
+    latency: Duration,
+    at: Instant,
+}
+
+/// Which of VLC's control interfaces to talk to.
+#[derive(Copy, Clone, Default, PartialEq, Eq, ValueEnum)]
+pub enum VlcProtocol {
+    /// RC/telnet line protocol (`--intf rc`)
+    #[default]
+    Rc,
+    /// HTTP interface (`--intf http`), commands are translated to its API
+    Http,
+    /// RC on the stdin/stdout of a VLC the controller starts itself (`--vlc-command`)
+    Stdin,
+}
+
+/// Connection settings for `VlcClient`.
+#[derive(Clone, Default)]
+pub struct VlcOptions {
+    /// Local address outbound connections are bound to
+    pub bind_addr: Option<IpAddr>,
+    /// VLC prints no banner/prompt (e.g. `--rc-quiet`), so don't wait for one.
+    pub no_banner: bool,
+    pub protocol: VlcProtocol,
+    /// Password of the HTTP interface
+    pub password: Option<String>,
+    /// Program and arguments VLC is started with under `VlcProtocol::Stdin`
+    pub command: Option<String>,
+    /// Repeated identical failures are summarised at most this often (zero logs all of them)
+    pub failure_log_interval: Duration,
+    /// Limit on establishing the connection, independent of how long replies may take
+    pub connect_timeout: Option<Duration>,
+    /// Tried, with the same settings, once retries against the primary address are exhausted
+    pub fallback_addr: Option<String>,
+    /// Error classes worth another attempt; other failures are returned at once
+    pub retry_on: Vec<ErrorClass>,
+    /// Shared by all commands and the fallback; when spent, failures are not retried
+    pub retry_budget: Option<Arc<RetryBudget>>,
+    /// Limit on one attempt (connect, command and reply), unless the verb has its own
+    pub response_timeout: Option<Duration>,
+    /// Per-verb limits overriding `response_timeout`
+    pub response_timeouts: HashMap<String, Duration>,
+    /// Largest banner or reply accepted before the connection is dropped; `None` is unlimited
+    pub max_response_bytes: Option<u64>,
+    /// Where to look up the address to connect to, instead of using the client's own
+    pub srv: Option<Arc<SrvResolver>>,
+}
+
+/// Where and how to reach VLC.
+pub struct VlcClient {
+    pub addr: String,
+    options: VlcOptions,
+    failure_log: FailureLog,
+    fallback: Option<Box<VlcClient>>,
+    last_connect: Mutex<Option<LastConnect>>,
+    stats: Mutex<BackendStats>,
+    /// The VLC started for `VlcProtocol::Stdin`, once the first command has started it
+    stdin_session: tokio::sync::Mutex<Option<stdin::Session>>,
+    stdin_reconnects: Mutex<stdin::ReconnectLog>,
+}
+
+impl VlcClient {
+    pub fn new(addr: String, options: VlcOptions) -> Self {
+        let failure_log = FailureLog::new(options.failure_log_interval);
+        let fallback = options.fallback_addr.clone().map(|fallback_addr| {
+            let options = VlcOptions {
+                fallback_addr: None,
+                srv: None,
+                ..options.clone()
+            };
+            Box::new(VlcClient::new(fallback_addr, options))
+        });
+        Self {
+            addr,
+            options,
+            failure_log,
+            fallback,
+            last_connect: Mutex::new(None),
+            stats: Mutex::default(),
+            stdin_session: tokio::sync::Mutex::new(None),
+            stdin_reconnects: Mutex::default(),
+        }
+    }
+
+    /// How the last connection attempt went, if there was one.
+    fn last_connect(&self) -> Option<ConnectReport> {
+        self.last_connect.lock().unwrap().as_ref().map(|last| ConnectReport {
+            ok: last.error.is_none(),
+            error: last.error.clone(),
+            latency_ms: last.latency.as_millis() as u64,
+            secs_ago: last.at.elapsed().as_secs(),
+        })
+    }
+
+    /// Forwards a command with retries, failing over to the fallback address
+    /// if the primary stays unreachable.
+    pub async fn forward_with_retry(&self, command: &[u8]) -> Result<String> {
+        let primary_error = match self.retry(command).await {
+            Ok(response) => {
+                debug!(target_addr = %self.addr, "Command served by primary VLC");
+                return Ok(response);
+            }
+            Err(e) => e,
+        };
+        let Some(fallback) = &self.fallback else {
+            return Err(primary_error);
+        };
+
+        // The primary failure itself was already logged (and throttled) by `retry`.
+        debug!(primary = %self.addr, fallback = %fallback.addr, "Primary VLC failed, trying fallback");
+        match fallback.retry(command).await {
+            Ok(response) => {
+                info!(target_addr = %fallback.addr, "Command served by fallback VLC");
+                Ok(response)
+            }
+            Err(e) => Err(anyhow::anyhow!(
+                "primary VLC failed ({}), fallback VLC failed ({})",
+                primary_error,
+                e
+            )),
+        }
+    }
+
+    // 3 attempts to connect to vlc then error. The first attempt is always
+    // made, and the last failure returned, whatever `max_retries` says.
+    async fn retry(&self, command: &[u8]) -> Result<String> {
+        let max_retries = 3;
+        let mut retry_delay = Duration::from_millis(100);
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match self.forward(command).await {
+                Ok(response) => {
+                    self.record_outcome(true);
+                    if let Some((failures, duration)) = self.failure_log.success()
+                        && failures > 1
+                    {
+                        info!(failures = failures, secs = duration.as_secs(), "VLC reachable again");
+                    }
+                    return Ok(response);
+                }
+                Err(e)
+                    if attempt < max_retries
+                        && self.options.retry_on.contains(&e.class())
+                        && self.take_retry_token() =>
+                {
+                    // Retries of a failure that is already being coalesced stay at debug.
+                    if self.failure_log.is_repeat(&e.to_string()) {
+                        debug!(address = %self.addr, attempt = attempt, error = %e, "VLC connection failed, retrying...");
+                    } else {
+                        warn!(
+                            address = %self.addr,
+                            attempt = attempt,
+                            class = e.class().as_str(),
+                            error = %e,
+                            delay_ms = retry_delay.as_millis(),
+                            "VLC connection failed, retrying..."
+                        );
+                    }
+                    tokio::time::sleep(retry_delay).await;
+                    retry_delay *= 2;
+                }
+                Err(e) => {
+                    self.record_outcome(false);
+                    match self.failure_log.failure(&e.to_string()) {
+                        Verdict::Log => {
+                            error!(
+                                address = %self.addr,
+                                attempts = attempt,
+                                class = e.class().as_str(),
+                                error = %e,
+                                "VLC connection failed permanently"
+                            );
+                        }
+                        Verdict::Summary { failures, window } => {
+                            error!(
+                                address = %self.addr,
+                                failures = failures,
+                                error = %e,
+                                "VLC unreachable: {} failures in last {}s",
+                                failures,
+                                window.as_secs()
+                            );
+                        }
+                        Verdict::Suppress => {
+                            debug!(address = %self.addr, attempts = attempt, error = %e, "VLC connection failed permanently");
+                        }
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    fn record_outcome(&self, ok: bool) {
+        let mut stats = self.stats.lock().unwrap();
+        if ok {
+            stats.successes += 1;
+            stats.last_success = Some(Instant::now());
+        } else {
+            stats.failures += 1;
+        }
+        stats.last_ok = Some(ok);
+    }
+
+    fn backend_status(&self, role: &'static str) -> BackendStatus {
+        let stats = self.stats.lock().unwrap();
+        BackendStatus {
+            address: self.addr.clone(),
+            role,
+            reachable: stats.last_ok,
+            successes: stats.successes,
+            failures: stats.failures,
+            last_contact_secs_ago: stats.last_success.map(|at| at.elapsed().as_secs()),
+            last_connect: self.last_connect(),
+        }
+    }
+
+    /// Takes a token from the shared retry budget, if there is one.
+    fn take_retry_token(&self) -> bool {
+        let Some(budget) = &self.options.retry_budget else {
+            return true;
+        };
+        let allowed = budget.try_acquire();
+        if !allowed {
+            debug!(address = %self.addr, "VLC retry budget exhausted, not retrying");
+        }
+        allowed
+    }
+
+    /// Sends one command over the configured protocol, within the response
+    /// timeout of its verb.
+    async fn forward(&self, command: &[u8]) -> Result<String, ForwardError> {
+        let Some(limit) = self.response_timeout(command) else {
+            return self.forward_once(command).await;
+        };
+        match tokio::time::timeout(limit, self.forward_once(command)).await {
+            Ok(result) => result,
+            Err(_) => Err(ForwardError::Io(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("no response from VLC within {}ms", limit.as_millis()),
+            ))),
+        }
+    }
+
+    fn response_timeout(&self, command: &[u8]) -> Option<Duration> {
+        let command = String::from_utf8_lossy(command);
+        let verb = command.split_whitespace().next().unwrap_or_default();
+        self.options.response_timeouts.get(verb).copied().or(self.options.response_timeout)
+    }
+
+    async fn forward_once(&self, command: &[u8]) -> Result<String, ForwardError> {
+        match self.options.protocol {
+            VlcProtocol::Rc => self.forward_rc(command).await,
+            VlcProtocol::Http => {
+                let command = std::str::from_utf8(command).map_err(|e| ForwardError::Protocol(e.into()))?;
+                Ok(self.forward_http(command.trim()).await?)
+            }
+            VlcProtocol::Stdin => self.forward_stdin(command).await,
+        }
+    }
+
+    /// Connects to VLC to forward a command, returning VLC's response without the prompt.
+    async fn forward_rc(&self, command: &[u8]) -> Result<String, ForwardError> {
+        let mut stream = self.connect().await?;
+        debug!(address = %self.addr, "Connected to VLC");
+        let (reader, mut writer) = stream.split();
+        let mut reader = BufReader::new(reader);
+
+        // Read the initial prompt
+        if !self.options.no_banner {
+            self.read_to_prompt(&mut reader, &mut Vec::new()).await?;
+            debug!("Read VLC initial prompt");
+        }
+        self.exchange(&mut reader, &mut writer, command).await
+    }
+
+    /// Writes a command to an RC session and reads the reply, returned without the prompt.
+    async fn exchange<R, W>(&self, reader: &mut R, writer: &mut W, command: &[u8]) -> Result<String, ForwardError>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        writer.write_all(command).await?;
+        debug!(command = %String::from_utf8_lossy(command).trim(), "Sent command to VLC");
+
+        let mut response_buf = Vec::new();
+        if closes_connection(command) {
+            self.read_to_close(reader, &mut response_buf).await?;
+        } else if self.options.no_banner {
+            // There may be no trailing prompt either: stop at the prompt, EOF or
+            // once VLC goes quiet. Bytes read before the timeout stay in the buffer.
+            let read = tokio::time::timeout(NO_BANNER_RESPONSE_TIMEOUT, self.read_to_prompt(reader, &mut response_buf));
+            if let Ok(result) = read.await {
+                result?;
+            } else {
+                debug!("No prompt from VLC, using the response read so far");
+            }
+        } else {
+            self.read_to_prompt(reader, &mut response_buf).await?;
+        }
+
+        let response = String::from_utf8_lossy(&response_buf);
+        let response = rc::strip_prompt(&response);
+        debug!(response = %response, "VLC response received\n");
+
+        Ok(response.to_string())
+    }
+
+    /// Reads up to and including the `>` prompt into `buf`, failing once more
+    /// than `max_response_bytes` arrive without one.
+    ///
+    /// The prompt is a `>` that starts a line. One inside a reply, as in a
+    /// title like `Q&A -> Part 2`, is part of the reply; stopping there would
+    /// hand the rest of the reply to whatever reads the connection next. Bytes
+    /// after the prompt stay in the reader's buffer for the next read.
+    async fn read_to_prompt<R: AsyncBufRead + Unpin>(&self, reader: &mut R, buf: &mut Vec<u8>) -> Result<(), ForwardError> {
+        let max = self.options.max_response_bytes.unwrap_or(u64::MAX);
+        loop {
+            let limit = max.saturating_add(1).saturating_sub(buf.len() as u64);
+            let read = (&mut *reader).take(limit).read_until(b'>', buf).await?;
+            if buf.len() as u64 > max {
+                return Err(response_too_large(max));
+            }
+            // EOF without a prompt: the caller gets what was read.
+            if read == 0 || buf.last() != Some(&b'>') || is_prompt(buf) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads until VLC closes the connection, for commands that end the session.
+    /// A reset counts as the close, since VLC may be exiting.
+    async fn read_to_close<R: AsyncBufRead + Unpin>(&self, reader: &mut R, buf: &mut Vec<u8>) -> Result<(), ForwardError> {
+        let max = self.options.max_response_bytes.unwrap_or(u64::MAX);
+        match reader.take(max.saturating_add(1)).read_to_end(buf).await {
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::BrokenPipe) => {
+                debug!(error = %e, "VLC reset the connection after a closing command");
+            }
+            Err(e) => return Err(e.into()),
+        }
+        if buf.len() as u64 > max {
+            return Err(response_too_large(max));
+        }
+        Ok(())
+    }
+
+    /// Opens the TCP connection, recording the outcome for `pi_diag`.
+    async fn connect(&self) -> Result<TcpStream, ForwardError> {
+        let started = Instant::now();
+        let result = self.connect_within_timeout().await;
+        *self.last_connect.lock().unwrap() = Some(LastConnect {
+            error: result.as_ref().err().map(ToString::to_string),
+            latency: started.elapsed(),
+            at: Instant::now(),
+        });
+        result
+    }
+
+    async fn connect_within_timeout(&self) -> Result<TcpStream, ForwardError> {
+        let Some(connect_timeout) = self.options.connect_timeout else {
+            return self.try_connect().await.map_err(ForwardError::Connect);
+        };
+        match tokio::time::timeout(connect_timeout, self.try_connect()).await {
+            Ok(result) => result.map_err(ForwardError::Connect),
+            Err(_) => Err(ForwardError::Connect(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("connecting to {} timed out after {}ms", self.addr, connect_timeout.as_millis()),
+            ))),
+        }
+    }
+
+    /// The `host:port` VLC is at: the `--vlc-srv` target, or the configured address.
+    async fn target_addr(&self) -> std::io::Result<String> {
+        match &self.options.srv {
+            Some(srv) => srv.target().await,
+            None => Ok(self.addr.clone()),
+        }
+    }
+
+    /// Opens the TCP connection, from `bind_addr` when one is configured.
+    async fn try_connect(&self) -> std::io::Result<TcpStream> {
+        let addr = self.target_addr().await?;
+        let Some(bind_addr) = self.options.bind_addr else {
+            return TcpStream::connect(&addr).await;
+        };
+
+        // The local address must be of the same family as the target.
+        let target = tokio::net::lookup_host(&addr)
+            .await?
+            .find(|target| target.is_ipv4() == bind_addr.is_ipv4())
+            .ok_or_else(|| std::io::Error::other(format!("{} has no address matching bind address {}", addr, bind_addr)))?;
+        let socket = if bind_addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket.bind(SocketAddr::new(bind_addr, 0))?;
+        socket.connect(target).await
+    }
+}
+
+/// True for RC commands after which VLC closes the connection without a prompt.
+fn closes_connection(command: &[u8]) -> bool {
+    let command = String::from_utf8_lossy(command);
+    command.split_whitespace().next().is_some_and(|verb| CLOSING_COMMANDS.contains(&verb))
+}
+
+/// `command` as one RC line: VLC only runs a command once it reads the `\n`,
+/// and a stray `\r` or second newline would be a command of its own. Trailing
+/// ones are dropped; one inside the command is an error, since whatever
+/// follows it was never checked as a command.
+pub fn command_line(command: &[u8]) -> Result<Vec<u8>> {
+    let end = command.iter().rposition(|b| !matches!(b, b'\r' | b'\n')).map_or(0, |last| last + 1);
+    if command[..end].iter().any(|b| matches!(b, b'\r' | b'\n')) {
+        anyhow::bail!("Command contains a line break");
+    }
+    let mut line = command[..end].to_vec();
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// True if the `>` ending `buf` is the prompt: nothing but blanks before it on its line.
+/// The space after the previous prompt is still unread when the next reply
+/// starts, so an empty reply reads as ` >`.
+fn is_prompt(buf: &[u8]) -> bool {
+    let before = &buf[..buf.len() - 1];
+    let line_start = before.iter().rposition(|b| *b == b'\n').map_or(0, |newline| newline + 1);
+    before[line_start..].iter().all(u8::is_ascii_whitespace)
+}
+
+/// Error for a reply over `--vlc-max-response-bytes`; the connection is dropped with it.
+fn response_too_large(max: u64) -> ForwardError {
+    ForwardError::Protocol(anyhow::anyhow!("VLC response exceeds {} bytes, connection dropped", max))
+}
+
+impl VlcTransport for VlcClient {
+    fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    fn forward_with_retry<'a>(&'a self, command: &'a [u8]) -> BoxFuture<'a, Result<String>> {
+        Box::pin(VlcClient::forward_with_retry(self, command))
+    }
+
+    fn diagnostics(&self) -> VlcDiagnostics {
+        let budget = self.options.retry_budget.as_ref().map(|budget| budget.remaining());
+        VlcDiagnostics {
+            srv_target: self.options.srv.as_ref().and_then(|srv| srv.current()),
+            last_connect: self.last_connect(),
+            fallback_address: self.fallback.as_ref().map(|fallback| fallback.addr.clone()),
+            fallback_last_connect: self.fallback.as_ref().and_then(|fallback| fallback.last_connect()),
+            retry_budget_remaining: budget.map(|(remaining, _)| remaining),
+            retry_budget_capacity: budget.map(|(_, capacity)| capacity),
+        }
+    }
+
+    fn backends(&self) -> Vec<BackendStatus> {
+        let mut backends = vec![self.backend_status("primary")];
+        backends.extend(self.fallback.as_ref().map(|fallback| fallback.backend_status("fallback")));
+        backends
+    }
+
+    fn reconnect(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            if self.options.protocol != VlcProtocol::Stdin {
+                return Ok(None);
+            }
+            let pid = self.restart_stdin().await?;
+            Ok(Some(match pid {
+                Some(pid) => format!("restarted VLC on stdin (pid {})", pid),
+                None => "restarted VLC on stdin".to_string(),
+            }))
+        })
+    }
+
+    fn connection_stats(&self) -> ConnectionStats {
+        if self.options.protocol != VlcProtocol::Stdin {
+            return ConnectionStats::default();
+        }
+        self.stdin_reconnects.lock().unwrap().stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replies to several commands read from one buffered connection, with a
+    /// buffer small enough that prompts and replies straddle its refills.
+    #[tokio::test]
+    async fn replies_keep_to_their_prompts() {
+        let stream: &[u8] = b"VLC media player 3.0.18 Vetinari\r\nCommand Line Interface initialized.\r\n> \
+            ( state playing )\r\n> \
+            > \
+            +----[ Playlist - playlist ]\r\n|   4 - Q&A -> Part 2 (00:01:10)\r\n+----[ End of playlist ]\r\n> \
+            ( audio volume: 256 )\r\n> ";
+        let client = VlcClient::new(String::new(), VlcOptions::default());
+        let mut reader = BufReader::with_capacity(5, stream);
+        let mut buf = Vec::new();
+        client.read_to_prompt(&mut reader, &mut buf).await.unwrap();
+
+        let mut replies = Vec::new();
+        for _ in 0..4 {
+            buf.clear();
+            client.read_to_prompt(&mut reader, &mut buf).await.unwrap();
+            replies.push(rc::strip_prompt(&String::from_utf8_lossy(&buf)).to_string());
+        }
+        assert_eq!(replies[0], "( state playing )");
+        assert_eq!(replies[1], "");
+        assert!(replies[2].contains("Q&A -> Part 2") && replies[2].ends_with("[ End of playlist ]"));
+        assert_eq!(replies[3], "( audio volume: 256 )");
+    }
+
+    /// One session serves commands until `quit` ends it; the next starts VLC again.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stdin_sessions_outlive_a_command() {
+        let script = std::env::temp_dir().join(format!("vlc-control-fake-vlc-{}.sh", std::process::id()));
+        let fake_vlc = "printf 'VLC media player\\r\\n> '\n\
+            while read -r line; do\n\
+              [ \"$line\" = quit ] && exit 0\n\
+              printf '( %s %s )\\r\\n> ' $$ \"$line\"\n\
+            done\n";
+        std::fs::write(&script, fake_vlc).unwrap();
+        let options = VlcOptions {
+            protocol: VlcProtocol::Stdin,
+            command: Some(format!("sh {}", script.display())),
+            ..VlcOptions::default()
+        };
+        let client = VlcClient::new("stdin".to_string(), options);
+
+        let pid = |reply: String| reply.trim_matches(['(', ')', ' ']).split(' ').next().unwrap().to_string();
+        let first = client.forward(b"status\n").await.unwrap();
+        assert!(first.ends_with(" status )"), "{}", first);
+        let second = client.forward(b"volume\n").await.unwrap();
+        assert_eq!(pid(first.clone()), pid(second));
+        assert_eq!(VlcTransport::connection_stats(&client).reconnects, 0);
+        client.forward(b"quit\n").await.unwrap();
+        let restarted = client.forward(b"status\n").await.unwrap();
+        assert_ne!(pid(first), pid(restarted.clone()));
+        let stats = VlcTransport::connection_stats(&client);
+        assert_eq!((stats.reconnects, stats.last_reconnect_reason), (1, Some(ReconnectReason::Quit)));
+        assert_eq!(stats.connection_age_secs, Some(0));
+
+        let reconnected = VlcTransport::reconnect(&client).await.unwrap().unwrap();
+        assert!(reconnected.starts_with("restarted VLC on stdin"), "{}", reconnected);
+        let after = client.forward(b"status\n").await.unwrap();
+        assert_ne!(pid(restarted), pid(after));
+        let stats = VlcTransport::connection_stats(&client);
+        assert_eq!((stats.reconnects, stats.last_reconnect_reason), (2, Some(ReconnectReason::Requested)));
+        assert!(stats.last_reconnect_timestamp_ms.is_some());
+        std::fs::remove_file(script).unwrap();
+    }
+
+    #[tokio::test]
+    async fn backends_count_their_own_outcomes() {
+        let fallback = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let fallback_addr = fallback.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = fallback.accept().await.unwrap();
+            stream.write_all(b"> ").await.unwrap();
+            let mut line = Vec::new();
+            BufReader::new(&mut stream).read_until(b'\n', &mut line).await.unwrap();
+            stream.write_all(b"( state playing )\r\n> ").await.unwrap();
+        });
+        // Nothing listens on port 1, so the primary is refused at once.
+        let options = VlcOptions {
+            fallback_addr: Some(fallback_addr.clone()),
+            ..VlcOptions::default()
+        };
+        let client = VlcClient::new("127.0.0.1:1".to_string(), options);
+        assert_eq!(client.forward_with_retry(b"status\n").await.unwrap(), "( state playing )");
+
+        let backends = VlcTransport::backends(&client);
+        assert_eq!((backends[0].role, backends[0].reachable, backends[0].failures), ("primary", Some(false), 1));
+        assert_eq!(backends[1].address, fallback_addr);
+        assert_eq!((backends[1].reachable, backends[1].successes), (Some(true), 1));
+        assert_eq!(backends[1].last_contact_secs_ago, Some(0));
+    }
+
+    #[test]
+    fn commands_end_in_exactly_one_newline() {
+        for command in [&b"play"[..], b"play\n", b"play\r\n", b"play\n\n", b"play\r"] {
+            assert_eq!(command_line(command).unwrap(), b"play\n");
+        }
+        assert_eq!(command_line(b"seek 10 \n").unwrap(), b"seek 10 \n");
+        for command in [&b"status\rquit"[..], b"status\nquit\n", b"\nplay"] {
+            assert!(command_line(command).is_err(), "{:?}", command);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_response_limit_spans_the_whole_reply() {
+        let options = VlcOptions {
+            max_response_bytes: Some(20),
+            ..VlcOptions::default()
+        };
+        let client = VlcClient::new(String::new(), options);
+        let mut reader = BufReader::new(&b"a -> b -> c -> d -> e -> f\r\n> "[..]);
+        let err = client.read_to_prompt(&mut reader, &mut Vec::new()).await.unwrap_err();
+        assert!(err.to_string().contains("exceeds 20 bytes"));
+    }
+}
@@ -0,0 +1,84 @@
+//! Typed failures of a single forward to VLC, classified for the retry policy.
+
+use clap::ValueEnum;
+use std::fmt;
+use std::io;
+
+/// Broad kind of a forwarding failure; `--vlc-retry-on` selects which are retried.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ErrorClass {
+    /// Connecting failed, typically because VLC is not running
+    Connect,
+    /// The connection broke while sending the command or reading the reply
+    Io,
+    /// VLC replied with something unexpected, e.g. an HTTP error status
+    Protocol,
+}
+
+impl ErrorClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorClass::Connect => "connect",
+            ErrorClass::Io => "io",
+            ErrorClass::Protocol => "protocol",
+        }
+    }
+}
+
+/// Why forwarding a command failed. Displays as the underlying error.
+#[derive(Debug)]
+pub enum ForwardError {
+    Connect(io::Error),
+    Io(io::Error),
+    Protocol(anyhow::Error),
+}
+
+impl ForwardError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            ForwardError::Connect(_) => ErrorClass::Connect,
+            ForwardError::Io(_) => ErrorClass::Io,
+            ForwardError::Protocol(_) => ErrorClass::Protocol,
+        }
+    }
+}
+
+impl fmt::Display for ForwardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForwardError::Connect(e) | ForwardError::Io(e) => e.fmt(f),
+            ForwardError::Protocol(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ForwardError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ForwardError::Connect(e) | ForwardError::Io(e) => Some(e),
+            ForwardError::Protocol(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl From<io::Error> for ForwardError {
+    fn from(e: io::Error) -> Self {
+        ForwardError::Io(e)
+    }
+}
+
+/// Recovers the class of an error that went through `anyhow`, as the HTTP
+/// client's errors do: connect failures keep their class, other I/O errors
+/// are `Io` and everything else is `Protocol`.
+impl From<anyhow::Error> for ForwardError {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<ForwardError>() {
+            Ok(forward_error) => return forward_error,
+            Err(e) => e,
+        };
+        match e.downcast::<io::Error>() {
+            Ok(io_error) => ForwardError::Io(io_error),
+            Err(e) => ForwardError::Protocol(e),
+        }
+    }
+}
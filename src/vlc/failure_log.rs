@@ -0,0 +1,88 @@
+//! Coalesces repeated, identical VLC failures so an outage does not flood the log.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Decides which VLC failures are worth a log line.
+///
+/// The first occurrence of an error is logged; identical errors after it are
+/// only counted, and reported as one summary once `interval` has passed.
+/// A different error, or a success, ends the streak. An `interval` of zero
+/// logs every failure.
+pub struct FailureLog {
+    interval: Duration,
+    state: Mutex<Option<Streak>>,
+}
+
+struct Streak {
+    error: String,
+    since: Instant,
+    last_logged: Instant,
+    /// Failures not logged since `last_logged`
+    suppressed: u64,
+    total: u64,
+}
+
+/// What the caller should log for a failure.
+pub enum Verdict {
+    /// Log the failure itself.
+    Log,
+    /// Log a summary in place of the failure.
+    Summary { failures: u64, window: Duration },
+    /// Stay quiet.
+    Suppress,
+}
+
+impl FailureLog {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: Mutex::new(None),
+        }
+    }
+
+    pub fn failure(&self, error: &str) -> Verdict {
+        if self.interval.is_zero() {
+            return Verdict::Log;
+        }
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        match state.as_mut() {
+            Some(streak) if streak.error == error => {
+                streak.total += 1;
+                streak.suppressed += 1;
+                let window = now.duration_since(streak.last_logged);
+                if window < self.interval {
+                    return Verdict::Suppress;
+                }
+                let failures = streak.suppressed;
+                streak.suppressed = 0;
+                streak.last_logged = now;
+                Verdict::Summary { failures, window }
+            }
+            _ => {
+                *state = Some(Streak {
+                    error: error.to_string(),
+                    since: now,
+                    last_logged: now,
+                    suppressed: 0,
+                    total: 1,
+                });
+                Verdict::Log
+            }
+        }
+    }
+
+    /// True while `error` is the failure already being coalesced.
+    pub fn is_repeat(&self, error: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state.as_ref().is_some_and(|streak| streak.error == error)
+    }
+
+    /// Ends the current streak, returning how many failures it had and how long it lasted.
+    pub fn success(&self) -> Option<(u64, Duration)> {
+        let streak = self.state.lock().unwrap().take()?;
+        Some((streak.total, streak.since.elapsed()))
+    }
+}
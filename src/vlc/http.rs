@@ -0,0 +1,301 @@
+//! VLC's HTTP interface, driven with the same command vocabulary as RC.
+//!
+//! Commands are translated to `/requests/status.json?command=...` calls.
+//! Queries (`status`, `get_time`, `playlist`, ...) are answered from the JSON
+//! API but rendered the way RC prints them, so callers need not care which
+//! protocol is in use.
+
+use anyhow::{Result, bail};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::debug;
+
+use super::VlcClient;
+
+impl VlcClient {
+    /// Executes one RC-style command against the HTTP interface.
+    pub(super) async fn forward_http(&self, command: &str) -> Result<String> {
+        let (verb, arg) = command
+            .split_once(char::is_whitespace)
+            .map(|(verb, arg)| (verb, arg.trim()))
+            .unwrap_or((command, ""));
+
+        let action = match (verb, arg) {
+            ("status", "") => {
+                let status = self.http_status(None).await?;
+                let playlist = self.http_get("/requests/playlist.json").await?;
+                return Ok(render_status(&status, &playlist));
+            }
+            ("get_time", "") => return Ok(int_field(&self.http_status(None).await?, "time")),
+            ("get_length", "") => return Ok(int_field(&self.http_status(None).await?, "length")),
+            ("volume", "") => return Ok(int_field(&self.http_status(None).await?, "volume")),
+            ("is_playing", "") => {
+                let status = self.http_status(None).await?;
+                let playing = status["state"].as_str() == Some("playing");
+                return Ok(if playing { "1" } else { "0" }.to_string());
+            }
+            ("get_title", "") => {
+                let status = self.http_status(None).await?;
+                let title = &status["information"]["category"]["meta"]["title"];
+                return Ok(title.as_str().unwrap_or_default().to_string());
+            }
+            ("playlist", "") => return Ok(render_playlist(&self.http_get("/requests/playlist.json").await?)),
+            ("fullscreen", "on" | "off") => {
+                // The HTTP API only toggles, so compare against the reported state first.
+                let status = self.http_status(None).await?;
+                let is_fullscreen = status["fullscreen"].as_bool().unwrap_or(status["fullscreen"] == 1);
+                if is_fullscreen == (arg == "on") {
+                    return Ok(String::new());
+                }
+                "fullscreen".to_string()
+            }
+            ("play", "") => "pl_play".to_string(),
+            ("pause", "") => "pl_pause".to_string(),
+            ("stop", "") => "pl_stop".to_string(),
+            ("next", "") => "pl_next".to_string(),
+            ("prev", "") => "pl_previous".to_string(),
+            ("clear", "") => "pl_empty".to_string(),
+            ("random", "") => "pl_random".to_string(),
+            ("loop", "") => "pl_loop".to_string(),
+            ("repeat", "") => "pl_repeat".to_string(),
+            ("fullscreen" | "f", "") => "fullscreen".to_string(),
+            ("add", uri) if !uri.is_empty() => format!("in_play&input={}", url_encode(uri)),
+            ("enqueue", uri) if !uri.is_empty() => format!("in_enqueue&input={}", url_encode(uri)),
+            ("seek", value) | ("volume", value) | ("rate", value) if !value.is_empty() => {
+                format!("{}&val={}", verb, url_encode(value))
+            }
+            _ => bail!("Command '{}' is not supported over the VLC HTTP interface", command),
+        };
+
+        self.http_status(Some(&action)).await?;
+        Ok(String::new())
+    }
+
+    /// Fetches `status.json`, optionally running a `command=` action first.
+    async fn http_status(&self, action: Option<&str>) -> Result<Value> {
+        match action {
+            Some(action) => self.http_get(&format!("/requests/status.json?command={}", action)).await,
+            None => self.http_get("/requests/status.json").await,
+        }
+    }
+
+    /// Performs a GET and parses the JSON body.
+    async fn http_get(&self, path: &str) -> Result<Value> {
+        let mut stream = self.connect().await?;
+        // With --vlc-srv, the host VLC is on rather than the SRV name.
+        let host = self.target_addr().await?;
+        debug!(address = %host, path = path, "Sending HTTP request to VLC");
+
+        // HTTP/1.0 keeps the reply simple: no chunked encoding, closed when done.
+        // The request holds the password, so it is never logged.
+        let mut request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n", path, host);
+        if let Some(password) = &self.options.password {
+            request.push_str(&authorization(password));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut raw = Vec::new();
+        let limit = self.options.max_response_bytes.unwrap_or(u64::MAX);
+        (&mut stream).take(limit.saturating_add(1)).read_to_end(&mut raw).await?;
+        if raw.len() as u64 > limit {
+            return Err(super::response_too_large(limit).into());
+        }
+        let raw = String::from_utf8_lossy(&raw);
+        let (head, body) = raw.split_once("\r\n\r\n").unwrap_or((&raw, ""));
+
+        let status_line = head.lines().next().unwrap_or_default();
+        let code = status_line.split_whitespace().nth(1).unwrap_or_default();
+        match code {
+            "200" => {}
+            "401" if self.options.password.is_some() => {
+                bail!("VLC HTTP interface rejected the password (401 Unauthorized), check --vlc-password")
+            }
+            "401" => bail!("VLC HTTP interface requires a password (401 Unauthorized), set --vlc-password"),
+            _ => bail!("VLC HTTP interface returned '{}'", status_line),
+        }
+        Ok(serde_json::from_str(body)?)
+    }
+}
+
+/// The header VLC expects: Basic auth with an empty user name. VLC has no
+/// user names, so any user would do, but the empty one is what it documents.
+fn authorization(password: &str) -> String {
+    format!("Authorization: Basic {}\r\n", base64(format!(":{}", password).as_bytes()))
+}
+
+/// Renders `status.json` as the RC `status` command would print it.
+///
+/// RC names the input by its URI, which `status.json` lacks, so it is taken
+/// from the current item of `playlist.json`, falling back to the metadata.
+fn render_status(status: &Value, playlist: &Value) -> String {
+    let mut lines = Vec::new();
+    let meta = &status["information"]["category"]["meta"];
+    let input = current_uri(playlist).or(meta["filename"].as_str()).or(meta["title"].as_str());
+    if let Some(name) = input {
+        lines.push(format!("( new input: {} )", name));
+    }
+    if let Some(volume) = status["volume"].as_i64() {
+        lines.push(format!("( audio volume: {} )", volume));
+    }
+    if let Some(state) = status["state"].as_str() {
+        lines.push(format!("( state {} )", state));
+    }
+    lines.join("\n")
+}
+
+/// The URI of the playlist item marked current, if any.
+fn current_uri(node: &Value) -> Option<&str> {
+    if node["current"].as_str() == Some("current")
+        && let Some(uri) = node["uri"].as_str()
+    {
+        return Some(uri);
+    }
+    node["children"].as_array().into_iter().flatten().find_map(current_uri)
+}
+
+/// Renders `playlist.json` as the RC `playlist` tree.
+fn render_playlist(root: &Value) -> String {
+    let mut lines = vec!["+----[ Playlist - playlist ]".to_string()];
+    // The root node is unnamed; its children are the playlist and media library.
+    for node in root["children"].as_array().into_iter().flatten() {
+        render_node(node, 0, &mut lines);
+    }
+    lines.push("+----[ End of playlist ]".to_string());
+    lines.join("\n")
+}
+
+fn render_node(node: &Value, depth: usize, lines: &mut Vec<String>) {
+    let marker = if node["current"].as_str() == Some("current") { "*" } else { "" };
+    lines.push(format!(
+        "| {}{}{} - {}",
+        "  ".repeat(depth),
+        marker,
+        node["id"].as_str().unwrap_or_default(),
+        node["name"].as_str().unwrap_or_default()
+    ));
+    for child in node["children"].as_array().into_iter().flatten() {
+        render_node(child, depth + 1, lines);
+    }
+}
+
+/// Prints an integer field like the RC getters do: empty when absent.
+fn int_field(status: &Value, field: &str) -> String {
+    status[field].as_i64().map(|n| n.to_string()).unwrap_or_default()
+}
+
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vlc::{VlcOptions, VlcProtocol};
+    use tokio::io::AsyncBufReadExt;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn encodes_the_password_with_an_empty_user() {
+        assert_eq!(authorization("secret"), "Authorization: Basic OnNlY3JldA==\r\n");
+        assert_eq!(authorization("pässwörd"), "Authorization: Basic OnDDpHNzd8O2cmQ=\r\n");
+    }
+
+    /// Answers one request with 200 if it carries `expected`, 401 otherwise.
+    async fn server(expected: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut head = String::new();
+                let mut reader = tokio::io::BufReader::new(&mut socket);
+                while reader.read_line(&mut head).await.unwrap() > 2 {}
+                let reply = if head.contains(expected) {
+                    "HTTP/1.0 200 OK\r\n\r\n{\"state\":\"playing\",\"volume\":256}"
+                } else {
+                    "HTTP/1.0 401 Unauthorized\r\n\r\n"
+                };
+                socket.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    fn client(addr: String, password: Option<&str>) -> VlcClient {
+        let options = VlcOptions {
+            protocol: VlcProtocol::Http,
+            password: password.map(str::to_string),
+            ..VlcOptions::default()
+        };
+        VlcClient::new(addr, options)
+    }
+
+    #[tokio::test]
+    async fn explains_rejected_passwords() {
+        let addr = server("Authorization: Basic OnNlY3JldA==").await;
+        assert_eq!(client(addr.clone(), Some("secret")).forward_http("volume").await.unwrap(), "256");
+
+        let wrong = client(addr.clone(), Some("guess")).forward_http("volume").await.unwrap_err().to_string();
+        assert!(wrong.contains("rejected the password") && !wrong.contains("guess"), "{}", wrong);
+        let missing = client(addr, None).forward_http("volume").await.unwrap_err().to_string();
+        assert!(missing.contains("set --vlc-password"), "{}", missing);
+    }
+
+    /// Answers `status.json` and `playlist.json` with canned bodies.
+    async fn vlc_server(status: &'static str, playlist: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut head = String::new();
+                let mut reader = tokio::io::BufReader::new(&mut socket);
+                while reader.read_line(&mut head).await.unwrap() > 2 {}
+                let body = if head.starts_with("GET /requests/playlist.json") { playlist } else { status };
+                socket.write_all(format!("HTTP/1.0 200 OK\r\n\r\n{}", body).as_bytes()).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn status_names_the_current_item_by_uri() {
+        let status = r#"{"state":"playing","volume":256,"information":{"category":{"meta":{"filename":"b.mp4"}}}}"#;
+        let playlist = r#"{"children":[{"id":"1","name":"Playlist","children":[
+            {"id":"3","name":"a.mp4","uri":"file:///media/a.mp4"},
+            {"id":"4","name":"b.mp4","uri":"file:///media/b.mp4","current":"current"}]}]}"#;
+        let addr = vlc_server(status, playlist).await;
+        let rendered = client(addr, None).forward_http("status").await.unwrap();
+        let input = crate::rc::parse_status(&rendered).input.unwrap();
+        assert_eq!(input, "file:///media/b.mp4");
+        assert!(crate::same_media(&input, "/media/b.mp4"));
+
+        // Without a current item, the metadata still names the input.
+        let addr = vlc_server(status, r#"{"children":[]}"#).await;
+        let rendered = client(addr, None).forward_http("status").await.unwrap();
+        assert!(rendered.contains("( new input: b.mp4 )"), "{}", rendered);
+    }
+}
@@ -0,0 +1,54 @@
+//! Caps the retry rate against VLC across all concurrent commands, so an
+//! outage does not turn every queued command into its own connection storm.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token bucket shared by every forward: each retry takes a token, and
+/// tokens refill evenly so that at most `retries` are spent per `window`.
+pub struct RetryBudget {
+    capacity: f64,
+    refill_per_sec: f64,
+    state: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RetryBudget {
+    pub fn new(retries: u32, window: Duration) -> Self {
+        let capacity = f64::from(retries);
+        Self {
+            capacity,
+            refill_per_sec: capacity / window.as_secs_f64().max(f64::MIN_POSITIVE),
+            state: Mutex::new(Bucket {
+                tokens: capacity,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Whole retries left in the budget right now, and its size.
+    pub fn remaining(&self) -> (u32, u32) {
+        let bucket = self.state.lock().unwrap();
+        let elapsed = bucket.refilled.elapsed().as_secs_f64();
+        let tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        (tokens as u32, self.capacity as u32)
+    }
+
+    /// Takes a token for one retry, or returns false if the budget is spent.
+    pub fn try_acquire(&self) -> bool {
+        let mut bucket = self.state.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.refilled = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
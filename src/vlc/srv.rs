@@ -0,0 +1,310 @@
+//! `--vlc-srv`: find VLC through a DNS SRV record instead of a fixed address.
+//!
+//! The record is looked up with the nameservers of `/etc/resolv.conf` and
+//! cached for its TTL, so a moved VLC is picked up by the next connection
+//! after the TTL runs out. Of several records the one with the lowest
+//! priority wins, then the highest weight.
+
+use std::hash::{BuildHasher, RandomState};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tracing::{debug, info};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const DNS_PORT: u16 = 53;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Lower bound on the cache lifetime, so a zero TTL does not mean a lookup per command
+const MIN_TTL: Duration = Duration::from_secs(5);
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// Resolves and caches the `host:port` an SRV name points at.
+pub struct SrvResolver {
+    name: String,
+    cached: Mutex<Option<Cached>>,
+}
+
+struct Cached {
+    target: String,
+    expires: Instant,
+}
+
+/// One SRV answer.
+#[derive(Debug, PartialEq, Eq)]
+struct SrvRecord {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
+    ttl: u32,
+}
+
+impl SrvResolver {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// The `host:port` to connect to, looked up again once the TTL has passed.
+    pub async fn target(&self) -> Result<String> {
+        if let Some(cached) = &*self.cached.lock().unwrap()
+            && cached.expires > Instant::now()
+        {
+            return Ok(cached.target.clone());
+        }
+        let record = self.lookup().await?;
+        let target = format!("{}:{}", record.target, record.port);
+        let ttl = Duration::from_secs(record.ttl.into()).max(MIN_TTL);
+
+        let mut cached = self.cached.lock().unwrap();
+        if cached.as_ref().is_none_or(|cached| cached.target != target) {
+            info!(srv = %self.name, target = %target, ttl_secs = ttl.as_secs(), "Resolved VLC address from SRV record");
+        }
+        *cached = Some(Cached {
+            target: target.clone(),
+            expires: Instant::now() + ttl,
+        });
+        Ok(target)
+    }
+
+    /// Last resolved `host:port`, if any.
+    pub fn current(&self) -> Option<String> {
+        self.cached.lock().unwrap().as_ref().map(|cached| cached.target.clone())
+    }
+
+    async fn lookup(&self) -> Result<SrvRecord> {
+        let mut last_error = Error::other("no nameserver configured");
+        for server in nameservers() {
+            match query(server, &self.name).await {
+                Ok(records) => {
+                    return best(records)
+                        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no SRV record for {}", self.name)));
+                }
+                Err(e) => {
+                    debug!(server = %server, srv = %self.name, error = %e, "SRV lookup failed");
+                    last_error = e;
+                }
+            }
+        }
+        Err(Error::new(last_error.kind(), format!("SRV lookup of {} failed: {}", self.name, last_error)))
+    }
+}
+
+/// Nameservers from `/etc/resolv.conf`, or the local one if it lists none.
+fn nameservers() -> Vec<SocketAddr> {
+    let conf = std::fs::read_to_string(RESOLV_CONF).unwrap_or_default();
+    let servers: Vec<SocketAddr> = conf
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        .collect();
+    if servers.is_empty() {
+        return vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), DNS_PORT)];
+    }
+    servers
+}
+
+async fn query(server: SocketAddr, name: &str) -> Result<Vec<SrvRecord>> {
+    let bind: SocketAddr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+    let socket = UdpSocket::bind(bind).await?;
+
+    // An answer naming another host redirects every command, so it must be
+    // hard to forge: the id is random, the source port is picked by the OS,
+    // and only replies from the queried server with that id are accepted.
+    let id = query_id();
+    socket.send_to(&encode_query(id, name)?, server).await?;
+    let mut buf = vec![0; 4096];
+    tokio::time::timeout(QUERY_TIMEOUT, async {
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await?;
+            let reply = &buf[..len];
+            if from != server || reply.get(..2) != Some(&id.to_be_bytes()[..]) {
+                debug!(server = %server, from = %from, "Ignoring DNS reply that does not match the query");
+                continue;
+            }
+            return parse_response(id, reply);
+        }
+    })
+    .await
+    .map_err(|_| Error::new(ErrorKind::TimedOut, format!("no answer from {}", server)))?
+}
+
+/// A random DNS message id. `RandomState` keys its hasher from the OS's
+/// random source, so the hash of anything is unpredictable.
+fn query_id() -> u16 {
+    RandomState::new().hash_one(std::process::id()) as u16
+}
+
+/// The record to use: lowest priority, then highest weight.
+fn best(records: Vec<SrvRecord>) -> Option<SrvRecord> {
+    records.into_iter().min_by_key(|record| (record.priority, std::cmp::Reverse(record.weight)))
+}
+
+fn encode_query(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut packet = Vec::with_capacity(name.len() + 18);
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid SRV name {}", name)));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(packet)
+}
+
+fn parse_response(id: u16, packet: &[u8]) -> Result<Vec<SrvRecord>> {
+    let malformed = || Error::new(ErrorKind::InvalidData, "malformed DNS response");
+    let header = packet.get(..12).ok_or_else(malformed)?;
+    if u16::from_be_bytes([header[0], header[1]]) != id {
+        return Err(Error::new(ErrorKind::InvalidData, "DNS response does not match the query"));
+    }
+    if header[2] & 0x02 != 0 {
+        return Err(Error::new(ErrorKind::InvalidData, "DNS response truncated"));
+    }
+    match header[3] & 0x0f {
+        0 => {}
+        3 => return Ok(Vec::new()),
+        rcode => return Err(Error::other(format!("DNS server answered with rcode {}", rcode))),
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(packet, pos).ok_or_else(malformed)? + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(packet, pos).ok_or_else(malformed)?;
+        let fixed = packet.get(pos..pos + 10).ok_or_else(malformed)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdlength = usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+        let rdata = pos + 10;
+        if packet.len() < rdata + rdlength {
+            return Err(malformed());
+        }
+        // Answers may also hold the CNAME chain leading to the records.
+        if rtype == TYPE_SRV && rdlength >= 7 {
+            let field = |at: usize| u16::from_be_bytes([packet[rdata + at], packet[rdata + at + 1]]);
+            records.push(SrvRecord {
+                priority: field(0),
+                weight: field(2),
+                port: field(4),
+                target: read_name(packet, rdata + 6).ok_or_else(malformed)?,
+                ttl,
+            });
+        }
+        pos = rdata + rdlength;
+    }
+    // A target of "." means the service is explicitly not available.
+    records.retain(|record| !record.target.is_empty());
+    Ok(records)
+}
+
+/// Position just past the (possibly compressed) name starting at `pos`.
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len => pos += 1 + usize::from(len),
+        }
+    }
+}
+
+/// Decodes the name at `pos`, following compression pointers.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<String> {
+    let mut labels = Vec::new();
+    // Bounds the pointer chain, which a hostile packet could make circular.
+    for _ in 0..128 {
+        let len = *packet.get(pos)?;
+        match len {
+            0 => return Some(labels.join(".")),
+            len if len & 0xc0 == 0xc0 => {
+                pos = usize::from(u16::from_be_bytes([len & 0x3f, *packet.get(pos + 1)?]));
+            }
+            len => {
+                let label = packet.get(pos + 1..pos + 1 + usize::from(len))?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + usize::from(len);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A response to `encode_query(7, "_vlc._tcp.example")` with two SRV
+    /// answers, the second target compressed against the first.
+    fn response() -> Vec<u8> {
+        let mut packet = encode_query(7, "_vlc._tcp.example").unwrap();
+        packet[2] = 0x81;
+        packet[3] = 0x80;
+        packet[7] = 2;
+        for (priority, weight, port, target) in [(20u16, 0u16, 4212u16, &b"\x04pi-a\x07example\x00"[..]), (10, 5, 54322, b"\x04pi-b\xc0\x16")] {
+            packet.extend_from_slice(&[0xc0, 0x0c]);
+            packet.extend_from_slice(&TYPE_SRV.to_be_bytes());
+            packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+            packet.extend_from_slice(&300u32.to_be_bytes());
+            packet.extend_from_slice(&(6 + target.len() as u16).to_be_bytes());
+            for field in [priority, weight, port] {
+                packet.extend_from_slice(&field.to_be_bytes());
+            }
+            packet.extend_from_slice(target);
+        }
+        packet
+    }
+
+    #[test]
+    fn picks_the_lowest_priority_record() {
+        let records = parse_response(7, &response()).unwrap();
+        assert_eq!(records.len(), 2);
+        let best = best(records).unwrap();
+        assert_eq!((best.target.as_str(), best.port, best.ttl), ("pi-b.example", 54322, 300));
+    }
+
+    #[tokio::test]
+    async fn ignores_replies_from_other_addresses() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut query = vec![0; 512];
+            let (_, client) = server.recv_from(&mut query).await.unwrap();
+            let mut reply = response();
+            reply[..2].copy_from_slice(&query[..2]);
+
+            // Same id, but no answers and not from the server asked.
+            let mut forged = reply.clone();
+            forged[7] = 0;
+            let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            spoofer.send_to(&forged, client).await.unwrap();
+            server.send_to(&reply, client).await.unwrap();
+        });
+
+        let records = query(server_addr, "_vlc._tcp.example").await.unwrap();
+        assert_eq!(best(records).unwrap().target, "pi-b.example");
+    }
+
+    #[test]
+    fn rejects_mismatched_and_truncated_responses() {
+        assert!(parse_response(8, &response()).is_err());
+        assert!(parse_response(7, &response()[..40]).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! `--vlc-protocol stdin`: VLC started by the controller, with its RC
+//! interface on the child's stdin and stdout instead of a TCP port.
+//!
+//! VLC is started on the first command and kept for the following ones. If
+//! it exits, or a reply cannot be read, the session ends (stopping that VLC)
+//! and the next command starts a new one. `pi_vlc_reconnect` replaces it at once.
+//! Each replacement is a reconnect, logged with its reason and counted for
+//! `pi_vlc_stats`.
+
+use serde::Serialize;
+use std::process::Stdio;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::BufReader;
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tracing::{debug, info, warn};
+
+use super::{ConnectionStats, ForwardError, VlcClient, closes_connection};
+
+/// Started when `--vlc-command` is not given. VLC's RC interface only reads a
+/// stdin that is not a terminal with `--rc-fake-tty`.
+const DEFAULT_COMMAND: &str = "vlc --intf rc --rc-fake-tty";
+
+/// A running VLC and the pipes to its RC interface.
+pub(super) struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    /// A reply may still be unread, because the last exchange was cut short by a timeout
+    unread_reply: bool,
+}
+
+/// Why a session ended, making the next command start VLC again.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconnectReason {
+    /// VLC exited, closing its end of the pipes
+    Eof,
+    /// A command could not be written to VLC's stdin
+    WriteError,
+    /// VLC's reply could not be read
+    ReadError,
+    /// The reply to a command that timed out never arrived
+    Timeout,
+    /// A command such as `quit` ended VLC
+    Quit,
+    /// `pi_vlc_reconnect`
+    Requested,
+}
+
+impl ReconnectReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            ReconnectReason::Eof => "eof",
+            ReconnectReason::WriteError => "write_error",
+            ReconnectReason::ReadError => "read_error",
+            ReconnectReason::Timeout => "timeout",
+            ReconnectReason::Quit => "quit",
+            ReconnectReason::Requested => "requested",
+        }
+    }
+
+    /// Classifies a failed exchange. Writing to a VLC that has gone breaks the pipe.
+    fn of(error: &ForwardError) -> Self {
+        match error {
+            ForwardError::Io(e) if e.kind() == std::io::ErrorKind::BrokenPipe => ReconnectReason::WriteError,
+            ForwardError::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => ReconnectReason::Eof,
+            _ => ReconnectReason::ReadError,
+        }
+    }
+}
+
+/// Session churn, for `pi_vlc_stats` and `/metrics`.
+#[derive(Default)]
+pub(super) struct ReconnectLog {
+    /// Why the last session ended, until the next one starts
+    ended: Option<ReconnectReason>,
+    reconnects: u64,
+    last_reconnect: Option<(SystemTime, ReconnectReason)>,
+    connected_at: Option<Instant>,
+}
+
+impl ReconnectLog {
+    pub(super) fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            persistent: true,
+            reconnects: self.reconnects,
+            last_reconnect_timestamp_ms: self
+                .last_reconnect
+                .map(|(at, _)| at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()),
+            last_reconnect_reason: self.last_reconnect.map(|(_, reason)| reason),
+            connection_age_secs: self.connected_at.map(|at| at.elapsed().as_secs()),
+        }
+    }
+}
+
+impl VlcClient {
+    /// Sends one command to the VLC on our pipes, starting it first if needed.
+    pub(super) async fn forward_stdin(&self, command: &[u8]) -> Result<String, ForwardError> {
+        let mut guard = self.stdin_session.lock().await;
+        if let Some(session) = guard.as_mut()
+            && let Ok(Some(status)) = session.child.try_wait()
+        {
+            warn!(status = %status, "VLC exited, starting it again");
+            *guard = None;
+            self.session_ended(ReconnectReason::Eof);
+        }
+        if guard.is_none() {
+            *guard = Some(self.start_vlc().await?);
+        }
+        let Some(session) = guard.as_mut() else {
+            unreachable!("session started above");
+        };
+
+        if session.unread_reply {
+            debug!("Discarding the reply to an earlier command");
+            if let Err(e) = self.read_to_prompt(&mut session.stdout, &mut Vec::new()).await {
+                *guard = None;
+                self.session_ended(ReconnectReason::Timeout);
+                return Err(e);
+            }
+        }
+        session.unread_reply = true;
+        let result = self.exchange(&mut session.stdout, &mut session.stdin, command).await;
+        session.unread_reply = false;
+        let ended = match &result {
+            Err(e) => Some(ReconnectReason::of(e)),
+            Ok(_) if closes_connection(command) => Some(ReconnectReason::Quit),
+            Ok(_) => None,
+        };
+        if let Some(reason) = ended {
+            *guard = None;
+            self.session_ended(reason);
+        }
+        result
+    }
+
+    /// Stops the VLC on our pipes, if one is running, and starts a new one,
+    /// returning its process ID.
+    pub(super) async fn restart_stdin(&self) -> Result<Option<u32>, ForwardError> {
+        let mut guard = self.stdin_session.lock().await;
+        if guard.take().is_some() {
+            info!("Stopping VLC to reconnect");
+        }
+        self.session_ended(ReconnectReason::Requested);
+        let session = self.start_vlc().await?;
+        let pid = session.child.id();
+        *guard = Some(session);
+        Ok(pid)
+    }
+
+    fn session_ended(&self, reason: ReconnectReason) {
+        let mut log = self.stdin_reconnects.lock().unwrap();
+        log.ended = Some(reason);
+        log.connected_at = None;
+    }
+
+    async fn start_vlc(&self) -> Result<Session, ForwardError> {
+        let command = self.options.command.as_deref().unwrap_or(DEFAULT_COMMAND);
+        let mut parts = command.split_whitespace();
+        let program = parts.next().ok_or_else(|| ForwardError::Connect(std::io::Error::other("--vlc-command is empty")))?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| ForwardError::Connect(std::io::Error::new(e.kind(), format!("starting '{}' failed: {}", command, e))))?;
+        info!(command = %command, pid = child.id(), "Started VLC with RC on stdin");
+
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            unreachable!("stdin and stdout are piped");
+        };
+        let mut session = Session {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+            unread_reply: false,
+        };
+        if !self.options.no_banner {
+            self.read_to_prompt(&mut session.stdout, &mut Vec::new()).await?;
+            debug!("Read VLC initial prompt");
+        }
+
+        let mut log = self.stdin_reconnects.lock().unwrap();
+        if let Some(reason) = log.ended.take() {
+            log.reconnects += 1;
+            log.last_reconnect = Some((SystemTime::now(), reason));
+            info!(reason = reason.as_str(), reconnects = log.reconnects, pid = session.child.id(), "Reconnected to VLC");
+        }
+        log.connected_at = Some(Instant::now());
+        Ok(session)
+    }
+}
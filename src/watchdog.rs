@@ -0,0 +1,96 @@
+//! Restarts VLC when forwarding keeps failing, e.g. because VLC has wedged.
+
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Counts consecutive failed VLC commands and decides when to restart VLC.
+///
+/// A restart is due after `threshold` consecutive failures within `window`,
+/// unless the previous restart happened less than `cooldown` ago.
+/// A `threshold` of zero disables the watchdog.
+pub struct Watchdog {
+    threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    failures: u32,
+    first_failure: Option<Instant>,
+    last_restart: Option<Instant>,
+    restarts: u64,
+}
+
+/// Point-in-time view of the watchdog, reported by `pi_status`.
+#[derive(Serialize)]
+pub struct WatchdogStatus {
+    pub enabled: bool,
+    pub consecutive_failures: u32,
+    pub threshold: u32,
+    pub restarts: u64,
+    pub secs_since_restart: Option<u64>,
+    pub in_cooldown: bool,
+}
+
+impl Watchdog {
+    pub fn new(threshold: u32, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            cooldown,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        state.first_failure = None;
+    }
+
+    /// Records a failed command, returning true if VLC should be restarted now.
+    pub fn record_failure(&self) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        match state.first_failure {
+            Some(first) if now.duration_since(first) <= self.window => {}
+            _ => {
+                state.failures = 0;
+                state.first_failure = Some(now);
+            }
+        }
+        state.failures += 1;
+
+        if state.failures < self.threshold || self.in_cooldown(&state) {
+            return false;
+        }
+        state.failures = 0;
+        state.first_failure = None;
+        state.last_restart = Some(now);
+        state.restarts += 1;
+        true
+    }
+
+    pub fn status(&self) -> WatchdogStatus {
+        let state = self.state.lock().unwrap();
+        WatchdogStatus {
+            enabled: self.threshold > 0,
+            consecutive_failures: state.failures,
+            threshold: self.threshold,
+            restarts: state.restarts,
+            secs_since_restart: state.last_restart.map(|at| at.elapsed().as_secs()),
+            in_cooldown: self.in_cooldown(&state),
+        }
+    }
+
+    fn in_cooldown(&self, state: &State) -> bool {
+        state.last_restart.is_some_and(|at| at.elapsed() < self.cooldown)
+    }
+}
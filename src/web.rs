@@ -0,0 +1,110 @@
+//! Minimal HTTP endpoint (`--metrics-address`) for monitoring and discovery.
+//!
+//! Routes:
+//! - `GET /metrics`: Prometheus metrics
+//! - `GET /commands`: the `list_commands` JSON
+//!
+//! Responses of at least `COMPRESS_MIN_BYTES` are gzipped for clients that
+//! send `Accept-Encoding: gzip`.
+
+use anyhow::Result;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::io::Write;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::debug;
+
+use crate::Controller;
+use crate::commands;
+
+/// Largest request head accepted, to bound memory per connection.
+const MAX_REQUEST_HEAD: usize = 8192;
+/// Smaller bodies are sent uncompressed; gzip would barely shrink them.
+const COMPRESS_MIN_BYTES: usize = 1024;
+
+/// Serves requests on `listener`, bound by the caller before privileges are dropped.
+pub async fn run_server(listener: TcpListener, controller: Arc<Controller>) -> Result<()> {
+    loop {
+        let (socket, client_addr) = listener.accept().await?;
+        let controller = controller.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(socket, &controller).await {
+                debug!(client_addr = %client_addr, error = %e, "Metrics request failed");
+            }
+        });
+    }
+}
+
+async fn handle_request(mut socket: TcpStream, controller: &Controller) -> Result<()> {
+    let (reader, mut writer) = socket.split();
+    let mut reader = BufReader::new(reader);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut accepts_gzip = false;
+    let mut head_len = request_line.len();
+    loop {
+        let mut header = String::new();
+        let read = reader.read_line(&mut header).await?;
+        head_len += read;
+        if read == 0 || header.trim().is_empty() || head_len > MAX_REQUEST_HEAD {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.trim().eq_ignore_ascii_case("accept-encoding")
+        {
+            accepts_gzip |= accepts_encoding(value, "gzip");
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            controller.metrics.render(&controller.breaker.status(), &controller.vlc.connection_stats()),
+        ),
+        (Some("GET"), Some("/commands")) => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string(&commands::list(&controller.mgmt_prefix))?,
+        ),
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "method not allowed\n".to_string()),
+    };
+
+    let (body, content_encoding) = if accepts_gzip && body.len() >= COMPRESS_MIN_BYTES {
+        (gzip(body.as_bytes())?, "Content-Encoding: gzip\r\n")
+    } else {
+        (body.into_bytes(), "")
+    };
+
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\n{}Content-Length: {}\r\nVary: Accept-Encoding\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        content_encoding,
+        body.len()
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(&body).await?;
+    Ok(())
+}
+
+/// True if an `Accept-Encoding` value allows `encoding` (not listed with `q=0`).
+fn accepts_encoding(value: &str, encoding: &str) -> bool {
+    value.split(',').any(|item| {
+        let mut params = item.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let refused = params.any(|param| param.strip_prefix("q=").is_some_and(|q| q.parse::<f32>() == Ok(0.0)));
+        (name.eq_ignore_ascii_case(encoding) || name == "*") && !refused
+    })
+}
+
+fn gzip(body: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    Ok(encoder.finish()?)
+}